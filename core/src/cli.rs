@@ -0,0 +1,296 @@
+//! Command line arguments of the `xynginc` binary
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Parser, Subcommand};
+
+use xynginc::mods::backup::Compression;
+use xynginc::mods::models::ConfigFormat;
+use xynginc::mods::output::OutputFormat;
+
+#[derive(Parser)]
+#[command(name = "xynginc")]
+#[command(version = "1.4.5")]
+#[command(about = "XyPriss Nginx Controller - Simplified Nginx and SSL management", long_about = None)]
+pub struct Cli {
+    /// Output format; with json, a single JSON document is printed on stdout and logs go to stderr
+    #[arg(long, global = true, value_parser = output_format(), default_value = "text")]
+    pub output: OutputFormat,
+
+    /// Prefix of every filesystem path (e.g. a sandbox directory), overrides XYNGINC_ROOT
+    #[arg(long, global = true)]
+    pub root: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+// The library enums don't depend on clap: the accepted values are listed here
+// and parsed with their own `parse`
+
+fn output_format() -> impl TypedValueParser<Value = OutputFormat> {
+    PossibleValuesParser::new(["text", "json"])
+        .try_map(|value| OutputFormat::parse(&value).ok_or_else(|| format!("invalid output format '{}'", value)))
+}
+
+fn config_format() -> impl TypedValueParser<Value = ConfigFormat> {
+    PossibleValuesParser::new(["json", "yaml", "toml"])
+        .try_map(|value| ConfigFormat::parse(&value).ok_or_else(|| format!("invalid config format '{}'", value)))
+}
+
+fn compression() -> impl TypedValueParser<Value = Compression> {
+    PossibleValuesParser::new(["none", "gzip", "zstd"])
+        .try_map(|value| Compression::parse(&value).ok_or_else(|| format!("invalid compression '{}'", value)))
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Apply configuration from a JSON, YAML or TOML file or stdin
+    Apply {
+        /// Path to config file (use '-' for stdin)
+        #[arg(short, long)]
+        config: String,
+
+        /// Config format, guessed from the file extension by default (required for stdin)
+        #[arg(long, value_parser = config_format())]
+        format: Option<ConfigFormat>,
+
+        /// Skip backup before applying
+        #[arg(long)]
+        no_backup: bool,
+
+        /// Apply the base config (nginx.conf, default site) even if it fails the nginx test; failing domains are still rejected
+        #[arg(long)]
+        force: bool,
+
+        /// Show what would change without touching the system (exits with 2 if there are changes)
+        #[arg(long)]
+        plan: bool,
+
+        /// Remove xynginc-managed sites that are no longer in the config
+        #[arg(long)]
+        prune: bool,
+    },
+
+    /// Check a config offline, without root and without touching the system
+    Validate {
+        /// Path to config file (use '-' for stdin)
+        #[arg(short, long)]
+        config: String,
+
+        /// Config format, guessed from the file extension by default (required for stdin)
+        #[arg(long, value_parser = config_format())]
+        format: Option<ConfigFormat>,
+    },
+
+    /// Print the JSON Schema of the config file
+    Schema,
+
+    /// Check system requirements (nginx, certbot)
+    Check,
+
+    /// Install and configure missing system requirements
+    Install,
+
+    /// List all configured domains
+    List,
+
+    /// Add a new domain configuration
+    Add {
+        /// Domain name (e.g., api.example.com)
+        #[arg(short, long)]
+        domain: String,
+
+        /// Port to proxy to
+        #[arg(short, long)]
+        port: u16,
+
+        /// Enable SSL with Let's Encrypt
+        #[arg(short, long)]
+        ssl: bool,
+
+        /// Email for Let's Encrypt (required if ssl=true)
+        #[arg(short, long)]
+        email: Option<String>,
+
+        /// Maximum client body size (e.g., 20M, 100M, 1G)
+        #[arg(long, default_value = "20M")]
+        max_body_size: String,
+    },
+
+    /// Remove a domain configuration
+    Remove {
+        /// Domain name to remove
+        domain: String,
+    },
+
+    /// Test nginx configuration
+    Test,
+
+    /// Reload nginx
+    Reload,
+
+    /// Show status of all domains
+    Status,
+
+    /// Clean broken or conflicting configurations
+    Clean {
+        /// Dry run (don't delete, just show)
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// List, inspect, take and prune backups
+    Backup {
+        #[command(subcommand)]
+        action: BackupCommands,
+    },
+
+    /// Restore from backup
+    Restore {
+        /// Backup to restore: identifier, label or 'latest', with an optional ~N (e.g. latest~2)
+        backup_id: String,
+
+        /// Restore only this site, leaving the others untouched
+        #[arg(long)]
+        domain: Option<String>,
+
+        /// Reload nginx once the restored configuration is in place
+        #[arg(long)]
+        reload: bool,
+    },
+
+    /// Manage local template overrides
+    Templates {
+        #[command(subcommand)]
+        action: TemplateCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommands {
+    /// List backups, most recent first
+    List,
+
+    /// Show the manifest of a backup
+    Show {
+        /// Backup to show: identifier, label or 'latest', with an optional ~N
+        backup_id: String,
+    },
+
+    /// Show how the sites changed since a backup
+    Diff {
+        /// Backup to compare: identifier, label or 'latest', with an optional ~N
+        backup_id: String,
+
+        /// Backup to compare with, or 'live' for the current configuration
+        #[arg(default_value = "live")]
+        other: String,
+    },
+
+    /// Take a backup now
+    Create {
+        /// Compression of the backup (default: the `compression` setting)
+        #[arg(long, value_parser = compression())]
+        compress: Option<Compression>,
+
+        /// Name to refer to the backup by (e.g. before-migration)
+        #[arg(long)]
+        label: Option<String>,
+    },
+
+    /// Delete the backups the retention rules do not keep
+    Prune {
+        /// Number of most recent backups to keep (default: the `keep_last` setting)
+        #[arg(long)]
+        keep_last: Option<u32>,
+
+        /// Also keep the last backup of each of the last N days (default: the `keep_daily` setting)
+        #[arg(long)]
+        keep_daily: Option<u32>,
+
+        /// Dry run (don't delete, just show)
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Delete backups
+    Delete {
+        /// Backups to delete: identifiers, labels or 'latest', with an optional ~N
+        #[arg(required = true)]
+        backup_ids: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TemplateCommands {
+    /// Export the embedded templates as a starting point for overrides
+    Export {
+        /// Target directory (default: /etc/xynginc/templates)
+        #[arg(short, long)]
+        dir: Option<String>,
+
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Show how local overrides diverge from the embedded templates
+    Diff {
+        /// Override directory (default: /etc/xynginc/templates)
+        #[arg(short, long)]
+        dir: Option<String>,
+    },
+}
+
+impl Commands {
+    /// Name of the command as typed on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::Apply { .. } => "apply",
+            Commands::Validate { .. } => "validate",
+            Commands::Schema => "schema",
+            Commands::Check => "check",
+            Commands::Install => "install",
+            Commands::List => "list",
+            Commands::Add { .. } => "add",
+            Commands::Remove { .. } => "remove",
+            Commands::Test => "test",
+            Commands::Reload => "reload",
+            Commands::Status => "status",
+            Commands::Clean { .. } => "clean",
+            Commands::Restore { .. } => "restore",
+            Commands::Backup { .. } => "backup",
+            Commands::Templates { .. } => "templates",
+        }
+    }
+
+    /// Whether the command changes the system and therefore needs root.
+    /// Read-only commands only need read access to the files they inspect.
+    pub fn requires_root(&self) -> bool {
+        match self {
+            Commands::Apply { plan, .. } => !plan,
+            Commands::Templates {
+                action: TemplateCommands::Export { dir, .. },
+            } => dir.is_none(),
+            Commands::Backup { action } => match action {
+                BackupCommands::List | BackupCommands::Show { .. } | BackupCommands::Diff { .. } => false,
+                BackupCommands::Prune { dry_run, .. } => !dry_run,
+                BackupCommands::Create { .. } | BackupCommands::Delete { .. } => true,
+            },
+            Commands::Validate { .. }
+            | Commands::Schema
+            | Commands::Check
+            | Commands::List
+            | Commands::Test
+            | Commands::Status
+            | Commands::Clean { dry_run: true }
+            | Commands::Templates { .. } => false,
+            Commands::Install
+            | Commands::Add { .. }
+            | Commands::Remove { .. }
+            | Commands::Reload
+            | Commands::Clean { .. }
+            | Commands::Restore { .. } => true,
+        }
+    }
+}
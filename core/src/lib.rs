@@ -0,0 +1,73 @@
+//! XyNginC - XyPriss Nginx Controller
+//!
+//! Library behind the `xynginc` command line tool: it turns a JSON, YAML or TOML
+//! description of domains into nginx sites, validates them with `nginx -t` in a staged copy
+//! of `/etc/nginx`, swaps them into place and manages Let's Encrypt certificates
+//! and backups.
+//!
+//! The functions re-exported at the crate root form the public API:
+//!
+//! - configuration model: [`Config`], [`DomainConfig`], [`DomainDefaults`], [`ConfigFormat`], [`parse_config`],
+//!   [`read_config`], [`interpolate`]
+//! - validation: [`validate`], [`check_config`], [`normalize_domain`], [`config_schema`]
+//! - planning: [`build_plan`], [`Plan`]
+//! - generation: [`render_nginx_config`], [`apply`], [`add_domain_config`], [`remove_domain`]
+//! - backups: [`create_backup`], [`list_backups`], [`restore_backup`], [`read_manifest`], [`prune_backups`],
+//!   [`delete_backups`], [`resolve_backup`], [`diff_backup`], [`restore_site`]
+//! - certificates: [`setup_ssl`], [`certificate_exists`], [`certificate_expiry`]
+//! - nginx control: [`test_nginx`], [`reload_nginx`], [`service_manager`], [`Stage`]
+//! - filesystem layout: [`Paths`], [`init_paths`], [`Layout`]
+//!
+//! Every fallible function returns an [`Error`] carrying a stable identifier
+//! and exit code; reports that can partially fail implement [`CommandReport`].
+//! Progress is logged to stdout, or to stderr after [`set_logs_to_stderr`].
+//!
+//! ```no_run
+//! use xynginc::{apply, parse_config, ApplyOptions, ConfigFormat};
+//!
+//! let config = parse_config(
+//!     r#"{ "domains": [{ "domain": "api.example.com", "port": 3000 }] }"#,
+//!     ConfigFormat::Json,
+//! )?;
+//! let report = apply(&config, &ApplyOptions { prune: true, ..Default::default() })?;
+//! for result in &report.domains {
+//!     println!("{}: {:?}", result.domain, result.status);
+//! }
+//! # Ok::<(), xynginc::Error>(())
+//! ```
+//!
+//! Paths default to the Debian layout; call [`init_paths`] with [`Paths::load`]
+//! (or a custom [`Paths`]) before anything else to use another one. The site
+//! [`Layout`] (sites-enabled or conf.d) is detected unless [`Paths::layout`] is set.
+//!
+//! The modules under [`mods`] stay reachable for lower-level needs.
+
+pub mod mods;
+
+pub use mods::apply::{
+    apply, apply_config, parse_config, read_config, validate_config, ApplyOptions, ApplyReport, DomainResult,
+    DomainStatus,
+};
+pub use mods::backup::{
+    create_backup, delete_backups, list_backups, open_backup, prune_backups, read_manifest, resolve_backup,
+    BackupManifest, BackupSettings, Compression, OpenedBackup,
+};
+pub use mods::config::render_nginx_config;
+pub use mods::domain::{add_domain, add_domain_config, list_domains, remove_domain, DomainInfo, DomainList};
+pub use mods::error::{Error, Result};
+pub use mods::interpolate::interpolate;
+pub use mods::layout::{layout, Layout};
+pub use mods::logger::set_logs_to_stderr;
+pub use mods::models::{Config, ConfigFormat, DomainConfig, DomainDefaults};
+pub use mods::nginx::{reload_nginx, test_nginx};
+pub use mods::output::CommandReport;
+pub use mods::paths::{init_paths, paths, Paths};
+pub use mods::plan::{build_plan, ChangeKind, FileChange, Plan, RejectedDomain};
+pub use mods::restore::{restore_backup, restore_site, RestoreReport};
+pub use mods::service::{service_manager, ServiceKind, ServiceManager};
+pub use mods::snapshot::{diff_backup, BackupDiff, SiteDiff};
+pub use mods::ssl::{certificate_exists, certificate_expiry, setup_ssl};
+pub use mods::stage::Stage;
+pub use mods::validate::{
+    check_config, config_schema, normalize_domain, validate, ConfigSchema, ValidationIssue, ValidationReport,
+};
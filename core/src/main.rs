@@ -1,55 +1,154 @@
+//! Command line front-end of the xynginc library
+
 use clap::Parser;
 
+mod cli;
 mod requirements;
-mod mods;
-
-use mods::apply::apply_config;
-use mods::backup::restore_backup;
-use mods::check::check_requirements;
-use mods::cleanup::clean_broken_configs;
-use mods::cli::{Cli, Commands};
-use mods::domain::{add_domain, list_domains, remove_domain};
-use mods::logger::log_error;
-use mods::nginx::{reload_nginx, show_status, test_nginx};
+
+use cli::{BackupCommands, Cli, Commands, TemplateCommands};
 use requirements::interactive_install;
+use xynginc::mods::apply::{apply_config, validate_config, ApplyOptions};
+use xynginc::mods::backup::{backup_now, delete_backups, prune_backups, show_backup, show_backups};
+use xynginc::mods::check::check_requirements;
+use xynginc::mods::cleanup::clean_broken_configs;
+use xynginc::mods::domain::{add_domain, list_domains, remove_domain};
+use xynginc::mods::error::Error;
+use xynginc::mods::logger::{log_error, set_logs_to_stderr};
+use xynginc::mods::nginx::{reload_nginx, show_status, test_nginx};
+use xynginc::mods::output::{finish, OutputFormat};
+use xynginc::mods::paths::{init_paths, paths, Paths};
+use xynginc::mods::plan::{plan_config, EXIT_PLAN_CHANGES};
+use xynginc::mods::restore::{restore_backup, restore_site};
+use xynginc::mods::snapshot::diff_backup;
+use xynginc::mods::template::{diff_templates, export_templates};
+use xynginc::mods::validate::config_schema;
 
 fn main() {
     let cli = Cli::parse();
+    let format = cli.output;
+    let command = cli.command.name();
+
+    // With JSON output, stdout only carries the final document
+    if format == OutputFormat::Json {
+        set_logs_to_stderr(true);
+    }
 
-    // Check if running as root
-    if !is_root() {
-        log_error("❌ Error: XyNginC requires root privileges");
-        log_error("   Please run with sudo: sudo xynginc ...");
-        std::process::exit(1);
+    // Resolve the filesystem layout before any module touches it
+    if let Err(e) = Paths::load(cli.root.as_deref()).and_then(init_paths) {
+        std::process::exit(finish(format, command, Err::<(), _>(e)));
     }
 
-    let result = match &cli.command {
-        Commands::Apply { config, no_backup, force } => apply_config(config, *no_backup, *force),
-        Commands::Check => check_requirements(),
-        Commands::Install => install_requirements(),
-        Commands::List => list_domains(),
+    // Check if running as root (not needed for read-only commands, nor against a sandbox copy of nginx)
+    if cli.command.requires_root() && !is_root() && is_system_nginx() {
+        let error = Error::PermissionDenied("XyNginC requires root privileges".to_string());
+        if format == OutputFormat::Text {
+            log_error("❌ Error: XyNginC requires root privileges");
+            log_error("   Please run with sudo: sudo xynginc ...");
+            std::process::exit(error.exit_code());
+        }
+        std::process::exit(finish(format, command, Err::<(), _>(error)));
+    }
+
+    let code = match &cli.command {
+        Commands::Apply {
+            config,
+            format: config_format,
+            plan: true,
+            prune,
+            ..
+        } => {
+            let result = plan_config(config, *config_format, *prune);
+            let has_changes = matches!(&result, Ok(plan) if plan.has_changes());
+            match finish(format, command, result) {
+                0 if has_changes => EXIT_PLAN_CHANGES,
+                code => code,
+            }
+        }
+        Commands::Apply {
+            config,
+            format: config_format,
+            no_backup,
+            force,
+            prune,
+            ..
+        } => {
+            let options = ApplyOptions {
+                no_backup: *no_backup,
+                force: *force,
+                prune: *prune,
+            };
+            finish(format, command, apply_config(config, *config_format, &options))
+        }
+        Commands::Validate {
+            config,
+            format: config_format,
+        } => finish(format, command, validate_config(config, *config_format)),
+        Commands::Schema => {
+            let schema = config_schema();
+            if format == OutputFormat::Text {
+                match serde_json::to_string_pretty(&schema) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => log_error(&format!("❌ Error: Failed to serialize the schema: {}", e)),
+                }
+            }
+            finish(format, command, Ok(schema))
+        }
+        Commands::Check => finish(format, command, check_requirements()),
+        Commands::Install => finish(format, command, install_requirements().map_err(Error::from)),
+        Commands::List => finish(format, command, list_domains()),
         Commands::Add {
             domain,
             port,
             ssl,
             email,
             max_body_size,
-        } => add_domain(domain, *port, *ssl, email.as_deref(), None, Some(max_body_size)),
-        Commands::Remove { domain } => remove_domain(domain),
-        Commands::Test => test_nginx(),
-        Commands::Reload => reload_nginx(),
-        Commands::Status => show_status(),
-        Commands::Clean { dry_run } => clean_broken_configs(*dry_run),
-        Commands::Restore { backup_id } => restore_backup(backup_id),
+        } => finish(
+            format,
+            command,
+            add_domain(domain, *port, *ssl, email.as_deref(), None, Some(max_body_size)),
+        ),
+        Commands::Remove { domain } => finish(format, command, remove_domain(domain)),
+        Commands::Test => finish(format, command, test_nginx()),
+        Commands::Reload => finish(format, command, reload_nginx()),
+        Commands::Status => finish(format, command, show_status()),
+        Commands::Clean { dry_run } => finish(format, command, clean_broken_configs(*dry_run)),
+        Commands::Backup { action } => match action {
+            BackupCommands::List => finish(format, command, show_backups()),
+            BackupCommands::Show { backup_id } => finish(format, command, show_backup(backup_id)),
+            BackupCommands::Diff { backup_id, other } => finish(format, command, diff_backup(backup_id, other)),
+            BackupCommands::Create { compress, label } => {
+                finish(format, command, backup_now(*compress, label.as_deref()))
+            }
+            BackupCommands::Prune {
+                keep_last,
+                keep_daily,
+                dry_run,
+            } => finish(format, command, prune_backups(*keep_last, *keep_daily, *dry_run)),
+            BackupCommands::Delete { backup_ids } => finish(format, command, delete_backups(backup_ids)),
+        },
+        Commands::Restore {
+            backup_id,
+            domain,
+            reload,
+        } => finish(
+            format,
+            command,
+            match domain {
+                Some(domain) => restore_site(backup_id, domain, *reload),
+                None => restore_backup(backup_id, *reload),
+            },
+        ),
+        Commands::Templates { action } => finish(
+            format,
+            command,
+            match action {
+                TemplateCommands::Export { dir, force } => export_templates(dir.as_deref(), *force),
+                TemplateCommands::Diff { dir } => diff_templates(dir.as_deref()),
+            },
+        ),
     };
 
-    match result {
-        Ok(_) => std::process::exit(0),
-        Err(e) => {
-            log_error(&format!("❌ Error: {}", e));
-            std::process::exit(1);
-        }
-    }
+    std::process::exit(code);
 }
 
 fn is_root() -> bool {
@@ -58,4 +157,11 @@ fn is_root() -> bool {
 
 fn install_requirements() -> Result<(), String> {
     interactive_install()
-}
\ No newline at end of file
+}
+
+/// Whether the nginx directory in use is the one of the system, whatever the
+/// root prefix: a prefix or a symlink leading back to it still requires root
+fn is_system_nginx() -> bool {
+    let canonical = |path: &str| std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+    canonical(&paths().nginx_dir) == canonical(&Paths::default().nginx_dir)
+}
@@ -1,37 +1,367 @@
+use serde::Serialize;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-use crate::mods::backup::{create_backup, restore_latest_backup};
-use crate::mods::cleanup::{detect_broken_configs, remove_config_files};
-use crate::mods::config::{config_exists, generate_nginx_config, ensure_nginx_main_config_exists, ensure_error_pages_exist};
-use crate::mods::domain::enable_site;
+use crate::mods::backup::{create_backup, prune_backups};
+use crate::mods::cleanup::detect_broken_configs;
+use crate::mods::config::{
+    config_exists, ensure_error_pages_exist, ensure_index_page_exists, render_default_config, render_nginx_config,
+};
+use crate::mods::error::{Error, Result};
+use crate::mods::interpolate::interpolate;
 use crate::mods::logger::{log_error, log_info, log_step, log_success, log_warning};
-use crate::mods::models::Config;
-use crate::mods::nginx::{reload_nginx, test_nginx_with_autofix};
-use crate::mods::ssl::setup_ssl;
+use crate::mods::marker::find_stale_sites;
+use crate::mods::models::{Config, ConfigFormat, DomainConfig, DomainDefaults};
+use crate::mods::nginx::{live_config, reload_nginx, test_nginx_with_autofix};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
+use crate::mods::ssl::{certificate_exists, setup_ssl};
+use crate::mods::stage::Stage;
+use crate::mods::template::load_template;
+use crate::mods::utils::camel_case;
+use crate::mods::validate::{canonical_names, check_domains, normalize_domain, validate, ValidationReport};
 
-pub fn apply_config(config_path: &str, no_backup: bool, force: bool) -> Result<(), String> {
-    log_step("> Applying configuration...");
+/// Outcome of a domain during apply
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DomainStatus {
+    Applied,
+    /// Served over HTTP although SSL was requested
+    FellBackToHttp {
+        reason: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<Error>,
+    },
+    /// Rejected, the previous live configuration of the domain is kept
+    Skipped { error: Error },
+    /// Rejected, the domain is not served
+    Failed { error: Error },
+}
+
+#[derive(Debug, Serialize)]
+pub struct DomainResult {
+    pub domain: String,
+    #[serde(flatten)]
+    pub status: DomainStatus,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ApplyReport {
+    pub domains: Vec<DomainResult>,
+    /// Broken configurations removed before applying
+    pub removed_broken: Vec<String>,
+    /// Managed sites removed by --prune
+    pub pruned: Vec<String>,
+}
+
+impl CommandReport for ApplyReport {
+    fn failure(&self) -> Option<Error> {
+        let failed = self
+            .domains
+            .iter()
+            .filter(|r| matches!(r.status, DomainStatus::Skipped { .. } | DomainStatus::Failed { .. }))
+            .count();
+        (failed > 0).then(|| {
+            Error::PartialApply(format!(
+                "{} of {} domain(s) could not be applied, the others are live",
+                failed,
+                self.domains.len()
+            ))
+        })
+    }
+}
+
+/// Options of an apply run, all disabled by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyOptions {
+    /// Skip the backup of /etc/nginx taken before any change
+    pub no_backup: bool,
+    /// Swap the staged base configuration (nginx.conf, default site, removals)
+    /// in even when it fails `nginx -t`. Domains failing the test are still rejected.
+    pub force: bool,
+    /// Remove the managed sites that are no longer in the config
+    pub prune: bool,
+}
+
+/// Parse a config written in the given format. Its `defaults` are filled
+/// into its domains; `include` entries are only resolved by `read_config`.
+pub fn parse_config(content: &str, format: ConfigFormat) -> Result<Config> {
+    parse_with_defaults(content, format, &DomainDefaults::default())
+        .map_err(|e| Error::ConfigParse(format!("Invalid {} config: {}", format.name(), e)))
+}
+
+/// Parse a config, its domains inheriting its `defaults`, then the ones of the including file
+fn parse_with_defaults(
+    content: &str,
+    format: ConfigFormat,
+    inherited: &DomainDefaults,
+) -> std::result::Result<Config, String> {
+    with_defaults(parse_value(content, format)?, inherited)
+}
+
+/// Parse a config into a generic value, so that the defaults and the
+/// references are handled the same way whatever the format
+fn parse_value(content: &str, format: ConfigFormat) -> std::result::Result<serde_json::Value, String> {
+    match format {
+        ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+    }
+}
+
+/// Fill the `defaults` of a parsed config, then the inherited ones, into its
+/// domains and deserialize it
+fn with_defaults(mut value: serde_json::Value, inherited: &DomainDefaults) -> std::result::Result<Config, String> {
+    let defaults: Option<DomainDefaults> = match value.get("defaults") {
+        Some(defaults) => serde_path_to_error::deserialize(defaults.clone())
+            .map_err(|e| with_suggestion(format!("defaults.{}", e)))?,
+        None => None,
+    };
+    let defaults = defaults.unwrap_or_default().or(inherited);
+    let defaults = serde_json::to_value(&defaults).map_err(|e| e.to_string())?;
+
+    if let (Some(domains), Some(defaults)) =
+        (value.get_mut("domains").and_then(|d| d.as_array_mut()), defaults.as_object())
+    {
+        for domain in domains.iter_mut().filter_map(|d| d.as_object_mut()) {
+            for (key, default) in defaults {
+                if !domain.contains_key(key) && !domain.contains_key(&camel_case(key)) {
+                    domain.insert(key.clone(), default.clone());
+                }
+            }
+        }
+    }
+
+    serde_path_to_error::deserialize(value).map_err(|e| with_suggestion(e.to_string()))
+}
+
+/// Add a "did you mean" hint to the unknown field errors of serde, e.g.
+/// "unknown field `max_body_sise`, expected one of `domain`, `port`, ..."
+fn with_suggestion(message: String) -> String {
+    let Some((_, rest)) = message.split_once("unknown field `") else {
+        return message;
+    };
+    let Some((unknown, expected)) = rest.split_once('`') else {
+        return message;
+    };
+
+    // Compare without case and separators, so that camelCase typos match too
+    let simplify = |name: &str| name.replace('_', "").to_lowercase();
+    let best = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (strsim::jaro_winkler(&simplify(unknown), &simplify(candidate)), candidate))
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+
+    match best {
+        Some((score, candidate)) if score >= 0.8 => format!("{} (did you mean `{}`?)", message.trim_end(), candidate),
+        _ => message,
+    }
+}
+
+/// Read and parse the config from a file or stdin ('-'), then the files it includes.
+/// The format defaults to the one of the file extension; stdin requires it.
+/// `${VAR}` and `${file:path}` references are replaced once parsed, before
+/// the config is deserialized.
+///
+/// The returned config holds the domains of every file, in include order, and
+/// its `include` list is empty. A domain defined in two files is an error.
+pub fn read_config(config_path: &str, format: Option<ConfigFormat>) -> Result<Config> {
+    let mut loaded = vec![];
+    if config_path != "-" {
+        loaded.push(fs::canonicalize(config_path).map_err(Error::io("Failed to read config file"))?);
+    }
+
+    let mut domains = vec![];
+    let mut config = load_config_file(config_path, format, &DomainDefaults::default(), &mut loaded, &mut domains)?;
+    check_duplicate_sources(&domains)?;
+    config.domains = domains.into_iter().map(|(domain, _)| domain).collect();
+    config.include.clear();
+
+    match loaded.len() {
+        0 | 1 => log_success(&format!("✓ Config parsed: {} domain(s)", config.domains.len())),
+        files => log_success(&format!("✓ Config parsed: {} domain(s) from {} files", config.domains.len(), files)),
+    }
+    Ok(config)
+}
+
+/// Parse one config file and, depth first, the files it includes. Domains are
+/// collected with the file defining them; a file already loaded is skipped.
+fn load_config_file(
+    config_path: &str,
+    format: Option<ConfigFormat>,
+    inherited: &DomainDefaults,
+    loaded: &mut Vec<PathBuf>,
+    domains: &mut Vec<(DomainConfig, String)>,
+) -> Result<Config> {
+    let format = match format.or_else(|| ConfigFormat::from_path(config_path)) {
+        Some(format) => format,
+        None if config_path == "-" => {
+            return Err(Error::ConfigParse(
+                "The format of a config read from stdin must be given with --format json|yaml|toml".to_string(),
+            ));
+        }
+        None => {
+            return Err(Error::ConfigParse(format!(
+                "Cannot tell the format of '{}' from its extension (.json, .yaml, .yml or .toml), use --format",
+                config_path
+            )));
+        }
+    };
 
     let config_content = if config_path == "-" {
         log_info("> Reading from stdin...");
         std::io::read_to_string(std::io::stdin())
-            .map_err(|e| format!("Failed to read stdin: {}", e))?
+            .map_err(Error::io("Failed to read stdin"))?
     } else {
-        fs::read_to_string(config_path).map_err(|e| format!("Failed to read config file: {}", e))?
+        fs::read_to_string(config_path).map_err(Error::io(&format!("Failed to read config file {}", config_path)))?
+    };
+
+    // Paths in stdin are relative to the working directory
+    let base_dir = match config_path {
+        "-" => Path::new(""),
+        path => Path::new(path).parent().unwrap_or(Path::new("")),
+    };
+    let invalid = |e: String| {
+        let source = if config_path == "-" { String::new() } else { format!(" {}", config_path) };
+        Error::ConfigParse(format!("Invalid {} config{}: {}", format.name(), source, e))
     };
+    let mut value = parse_value(&config_content, format).map_err(invalid)?;
+    interpolate(&mut value, Some(base_dir))?;
+    let config = with_defaults(value, inherited).map_err(invalid)?;
+
+    let source = if config_path == "-" { "stdin" } else { config_path };
+    domains.extend(config.domains.iter().map(|domain| (domain.clone(), source.to_string())));
 
-    let config: Config =
-        serde_json::from_str(&config_content).map_err(|e| format!("Invalid JSON config: {}", e))?;
+    let defaults = config.defaults.clone().unwrap_or_default().or(inherited);
+    for pattern in &config.include {
+        for file in expand_include(pattern, base_dir, source)? {
+            let canonical = fs::canonicalize(&file).map_err(Error::io(&format!("Failed to read {}", file.display())))?;
+            if loaded.contains(&canonical) {
+                continue;
+            }
+            loaded.push(canonical);
+            load_config_file(&file.to_string_lossy(), None, &defaults, loaded, domains)?;
+        }
+    }
+
+    Ok(config)
+}
+
+/// Files matched by an `include` entry, sorted. A plain path must exist,
+/// a glob pattern may match nothing.
+fn expand_include(pattern: &str, base_dir: &Path, source: &str) -> Result<Vec<PathBuf>> {
+    let path = base_dir.join(pattern);
+
+    if !pattern.contains(['*', '?', '[']) {
+        if !path.is_file() {
+            return Err(Error::ConfigParse(format!(
+                "File '{}' included by {} does not exist",
+                path.display(),
+                source
+            )));
+        }
+        return Ok(vec![path]);
+    }
+
+    let matches = glob::glob(&path.to_string_lossy())
+        .map_err(|e| Error::ConfigParse(format!("Invalid include pattern '{}' in {}: {}", pattern, source, e)))?;
+    let mut files: Vec<PathBuf> = matches.filter_map(|entry| entry.ok()).filter(|path| path.is_file()).collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reject a domain defined by two config files, since neither can tell which
+/// one wins. Duplicates inside a single file are reported by `validate`.
+fn check_duplicate_sources(domains: &[(DomainConfig, String)]) -> Result<()> {
+    let mut seen: Vec<(String, &str)> = vec![];
+    let mut duplicates = vec![];
+
+    for (domain, source) in domains {
+        // Invalid names are reported by the validation
+        let Ok(name) = normalize_domain(&domain.domain) else { continue };
+        match seen.iter().find(|(seen_name, _)| *seen_name == name) {
+            Some((_, first)) if first != source => {
+                duplicates.push(format!("'{}' is defined in {} and {}", name, first, source));
+            }
+            Some(_) => {}
+            None => seen.push((name, source)),
+        }
+    }
+
+    if duplicates.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidConfig(format!(
+            "Domain(s) defined in several config files: {}",
+            duplicates.join("; ")
+        )))
+    }
+}
+
+/// Check the config file at `config_path` (or stdin with '-') without touching
+/// the system: nothing is written and nginx is not run
+pub fn validate_config(config_path: &str, format: Option<ConfigFormat>) -> Result<ValidationReport> {
+    log_step("> Validating configuration...");
+
+    let config = read_config(config_path, format)?;
+    let issues = validate(&config);
+
+    if issues.is_empty() {
+        log_success("✓ Configuration is valid");
+    } else {
+        for issue in &issues {
+            log_error(&format!("   ❌ {}: {}", issue.field, issue.message));
+        }
+    }
+
+    Ok(ValidationReport {
+        domains: config.domains.len(),
+        issues,
+    })
+}
+
+/// Apply the config file at `config_path` (or stdin with '-')
+pub fn apply_config(config_path: &str, format: Option<ConfigFormat>, options: &ApplyOptions) -> Result<ApplyReport> {
+    log_step("> Applying configuration...");
 
-    log_success(&format!("✓ Config parsed: {} domain(s)", config.domains.len()));
+    let config = read_config(config_path, format)?;
+    apply(&config, options)
+}
+
+/// Stage, validate and swap in the nginx configuration of every domain of `config`.
+///
+/// Domains are validated one by one: the report tells which ones were applied,
+/// and `ApplyReport::failure` is set when some of them could not be.
+pub fn apply(config: &Config, options: &ApplyOptions) -> Result<ApplyReport> {
+    let ApplyOptions { no_backup, force, prune } = *options;
+
+    // Valider chaque domaine : un domaine invalide est rejeté sans rien écrire.
+    // Les noms canoniques (minuscules, punycode) servent de noms de fichiers.
+    let checked_domains = check_domains(config);
+    let canonical = canonical_names(config);
 
     // ÉTAPE 0: Créer un backup avant toute modification
     if !no_backup {
         log_step("\n> Creating backup...");
-        create_backup()?;
+        create_backup("apply")?;
     }
 
-    // ÉTAPE 1: Détecter et nettoyer les configs cassées
+    // ÉTAPE 1: Installer les modules nginx manquants (headers-more) signalés par
+    // le test de la config live : la validation de la copie ne modifie jamais le système
+    log_step("\n> Checking nginx modules...");
+    match test_nginx_with_autofix(live_config()) {
+        Ok(_) => log_success("✓ Live configuration is valid"),
+        Err(e) => log_warning(&format!("⚠️  Live configuration test failed, continuing with the staged one: {}", e)),
+    }
+
+    // ÉTAPE 1b: Préparer une copie du répertoire nginx, rien n'est modifié en live
+    log_step("\n> Staging configuration...");
+    let mut stage = Stage::new()?;
+    let mut report = ApplyReport::default();
+
+    // ÉTAPE 2: Détecter et retirer les configs cassées
     log_step("\n> Checking for broken configurations...");
     let broken_configs = detect_broken_configs()?;
     
@@ -39,120 +369,434 @@ pub fn apply_config(config_path: &str, no_backup: bool, force: bool) -> Result<(
         log_warning(&format!("⚠️  Found {} broken configuration(s)", broken_configs.len()));
         for broken in &broken_configs {
             log_info(&format!("   - {}", broken));
+            stage.remove_site(broken)?;
         }
-        
-        log_step("> Cleaning broken configurations...");
-        for broken in &broken_configs {
-            let _ = remove_config_files(broken); // Ignore errors
-        }
-        log_success("✓ Cleanup complete");
+        report.removed_broken = broken_configs;
     } else {
         log_success("✓ No broken configurations found");
     }
 
-    // ÉTAPE 2: Installer la configuration principale nginx
-    log_step("\n> Installing main nginx configuration...");
-    ensure_nginx_main_config_exists()?;
+    // ÉTAPE 3: Configuration principale nginx et site par défaut
+    log_step("\n> Staging main nginx configuration...");
+    stage.write_file(&paths().nginx_conf, &load_template("nginx_main.conf")?)?;
+    stage.write_site("default", &render_default_config()?)?;
+
+    // ÉTAPE 3b: Supprimer les sites gérés qui ne sont plus dans la config
+    if prune {
+        log_step("\n> Pruning domains no longer in the configuration...");
+        let stale_sites = find_stale_sites(&canonical)?;
+
+        if stale_sites.is_empty() {
+            log_success("✓ Nothing to prune");
+        }
+        for site in &stale_sites {
+            stage.remove_site(site)?;
+            log_info(&format!("   - {}", site));
+        }
+        report.pruned = stale_sites;
+    }
 
-    // ÉTAPE 3: Installer les pages d'erreur personnalisées
-    ensure_error_pages_exist(None)?;
+    // ÉTAPE 4: Tester la base avant d'ajouter les domaines
+    // Un échec ici ne peut pas être imputé à un domaine en particulier ; --force
+    // ne contourne que ce test, un domaine en échec est toujours rejeté
+    log_step("\n> Testing staged nginx configuration...");
+    match stage.validate() {
+        Ok(_) => log_success("✓ Base configuration is valid"),
+        Err(e) => {
+            if force {
+                log_warning("⚠️  Configuration test failed but --force is enabled");
+                log_warning(&format!("   Error: {}", e));
+                log_warning("   Domains can't be validated against it and will be rejected");
+            } else {
+                log_error("❌ Configuration test failed!");
+                log_error(&format!("   {}", e));
+                return Err(Error::NginxTest(
+                    "Configuration test failed. The live configuration was not modified.".to_string(),
+                ));
+            }
+        }
+    }
 
-    // ÉTAPE 5: Appliquer les nouvelles configurations
-    for domain_config in &config.domains {
+    // ÉTAPE 5: Préparer et tester chaque domaine isolément
+    // Les domaines SSL sans certificat démarrent en HTTP, certbot en a besoin
+    let mut pending_ssl = vec![];
+    for (domain_config, checked) in canonical.domains.iter().zip(checked_domains) {
         log_step(&format!("\n🌐 Processing: {}", domain_config.domain));
+
+        let domain_config = match checked {
+            Ok(checked) => checked,
+            Err(e) => {
+                log_error(&format!("❌ {}: {}", domain_config.domain, e));
+                let status = if normalize_domain(&domain_config.domain).is_ok() && config_exists(&domain_config.domain) {
+                    log_warning("   ⚠️  Keeping the current configuration of this domain");
+                    DomainStatus::Skipped { error: e }
+                } else {
+                    DomainStatus::Failed { error: e }
+                };
+                report.domains.push(DomainResult {
+                    domain: domain_config.domain.clone(),
+                    status,
+                });
+                continue;
+            }
+        };
+        let domain_config = &domain_config;
         
         // Vérifier si une config existe déjà
         if config_exists(&domain_config.domain) {
             log_info("> Configuration already exists, will be overwritten");
         }
         
-        // Vérifier si le domaine est une adresse IP
-        let is_ip = domain_config.domain.parse::<std::net::IpAddr>().is_ok();
-        
-        // Si SSL est demandé, générer d'abord une config HTTP temporaire
+        let mut staged_config = domain_config.clone();
+        let mut status = DomainStatus::Applied;
         if domain_config.ssl {
-            if is_ip {
+            if domain_config.domain.parse::<std::net::IpAddr>().is_ok() {
                 log_warning(&format!("⚠️  SSL requested for IP address '{}', but Let's Encrypt does not support IP addresses.", domain_config.domain));
                 log_warning("   Falling back to HTTP for this domain.");
-                
-                // Désactiver SSL pour cette entrée
-                let mut http_config = domain_config.clone();
-                http_config.ssl = false;
-                
-                generate_nginx_config(&http_config)?;
-                enable_site(&http_config.domain)?;
-            } else {
-                log_info("> SSL requested - generating temporary HTTP configuration first");
-                
-                // Créer une config temporaire sans SSL
-                let mut temp_config = domain_config.clone();
-                temp_config.ssl = false;
-                
-                generate_nginx_config(&temp_config)?;
-                enable_site(&temp_config.domain)?;
-                
-                // Recharger nginx pour que certbot puisse l'utiliser
-                log_info("> Reloading nginx for certbot validation...");
-                reload_nginx()?;
-                
-                // Obtenir le certificat SSL
-                match setup_ssl(domain_config) {
-                    Ok(_) => {
-                        // Maintenant générer la vraie config avec SSL
-                        log_info("> Generating final HTTPS configuration...");
-                        generate_nginx_config(domain_config)?;
-                        enable_site(&domain_config.domain)?;
-                    },
-                    Err(e) => {
-                        log_error(&format!("❌ SSL setup failed for {}: {}", domain_config.domain, e));
-                        log_warning("   ⚠️  Falling back to HTTP only for this domain due to SSL error.");
-                        
-                        // Revenir à la config HTTP (déjà générée plus haut, mais on s'assure qu'elle est active)
-                        // Pas besoin de régénérer car temp_config était déjà appliquée
-                        // Mais pour être sûr (au cas où setup_ssl aurait cassé quelque chose)
-                        let mut http_config = domain_config.clone();
-                        http_config.ssl = false;
-                        generate_nginx_config(&http_config)?;
-                        enable_site(&http_config.domain)?;
-                    }
-                }
+                staged_config.ssl = false;
+                status = DomainStatus::FellBackToHttp {
+                    reason: "Let's Encrypt does not support IP addresses".to_string(),
+                    error: None,
+                };
+            } else if !certificate_exists(&domain_config.domain) {
+                log_info("> SSL requested - staging temporary HTTP configuration first");
+                staged_config.ssl = false;
             }
-        } else {
-            // Pas de SSL, générer directement la config HTTP
-            generate_nginx_config(domain_config)?;
-            enable_site(&domain_config.domain)?;
         }
+
+        let snapshot = stage.snapshot_site(&domain_config.domain)?;
+        let status = match stage_domain(&mut stage, &staged_config) {
+            Ok(_) => {
+                if domain_config.ssl && !staged_config.ssl && matches!(status, DomainStatus::Applied) {
+                    pending_ssl.push((report.domains.len(), domain_config.clone()));
+                }
+                status
+            }
+            Err(e) => {
+                log_error(&format!("❌ {}: {}", domain_config.domain, e));
+                stage.revert_site(snapshot)?;
+                if config_exists(&domain_config.domain) {
+                    log_warning("   ⚠️  Keeping the current configuration of this domain");
+                    DomainStatus::Skipped { error: e }
+                } else {
+                    DomainStatus::Failed { error: e }
+                }
+            }
+        };
+        report.domains.push(DomainResult {
+            domain: domain_config.domain.clone(),
+            status,
+        });
     }
 
-    // ÉTAPE 6: Tester la configuration avant reload (avec auto-fix)
-    log_step("\n> Testing nginx configuration...");
-    match test_nginx_with_autofix() {
-        Ok(_) => log_success("✓ Configuration is valid"),
-        Err(e) => {
-            if force {
-                log_warning("⚠️  Configuration test failed but --force is enabled");
-                log_warning(&format!("   Error: {}", e));
-            } else {
-                log_error("❌ Configuration test failed!");
-                log_error(&format!("   {}", e));
-                log_step("\n🔄 Rolling back changes...");
-                
-                // Restaurer le backup
-                if !no_backup {
-                    restore_latest_backup()?;
+    // ÉTAPE 6: Basculer la copie validée en live
+    log_step("\n> Swapping staged configuration into place...");
+    stage.commit()?;
+    log_success("✓ Configuration swapped into place");
+
+    // ÉTAPE 7: Installer les pages d'erreur des domaines appliqués, une fois leur config en place
+    install_pages(&report.domains)?;
+
+    // ÉTAPE 8: Obtenir les certificats manquants puis passer ces domaines en HTTPS
+    if !pending_ssl.is_empty() {
+        // Recharger nginx pour que certbot puisse l'utiliser
+        log_info("\n> Reloading nginx for certbot validation...");
+        reload_nginx()?;
+
+        let mut ssl_stage = Stage::new()?;
+        for (index, domain_config) in &pending_ssl {
+            let index = *index;
+            let fallback = match setup_ssl(domain_config) {
+                Ok(_) => {
+                    log_info(&format!("> Staging final HTTPS configuration for {}...", domain_config.domain));
+                    let snapshot = ssl_stage.snapshot_site(&domain_config.domain)?;
+                    stage_domain(&mut ssl_stage, domain_config).err().map(|e| {
+                        let _ = ssl_stage.revert_site(snapshot);
+                        ("HTTPS configuration rejected", e)
+                    })
                 }
-                
-                return Err("Configuration test failed. Changes have been rolled back.".to_string());
+                Err(e) => Some(("SSL setup failed", e)),
+            };
+
+            if let Some((reason, error)) = fallback {
+                log_error(&format!("❌ {}: {}: {}", domain_config.domain, reason, error));
+                log_warning("   ⚠️  Falling back to HTTP only for this domain.");
+                report.domains[index].status = DomainStatus::FellBackToHttp {
+                    reason: reason.to_string(),
+                    error: Some(error),
+                };
             }
         }
+
+        if ssl_stage.has_changes() {
+            ssl_stage.commit()?;
+            log_success("✓ HTTPS configuration swapped into place");
+        }
     }
 
-    // ÉTAPE 7: Reload nginx si auto_reload est activé
+    // ÉTAPE 9: Reload nginx si auto_reload est activé
     if config.auto_reload {
         log_step("\n🔄 Auto-reload enabled");
         reload_nginx()?;
     }
 
-    log_success("\n✅ Configuration applied successfully!");
+    // ÉTAPE 10: Supprimer les anciens backups selon la politique de rétention
+    if !no_backup
+        && paths().backup.auto_prune
+        && report.failure().is_none()
+        && let Err(e) = prune_backups(None, None, false)
+    {
+        log_warning(&format!("⚠️  Failed to prune old backups: {}", e));
+    }
+
+    print_summary(&report.domains);
+
+    if report.failure().is_none() {
+        log_success("\n✅ Configuration applied successfully!");
+    }
+    Ok(report)
+}
+
+/// Stage the rendered config of a domain and its sites-enabled symlink,
+/// then test the staged tree with it. A domain failing the test is never
+/// forced in: the caller reverts it.
+fn stage_domain(stage: &mut Stage, domain_config: &DomainConfig) -> Result<()> {
+    stage.write_site(&domain_config.domain, &render_nginx_config(domain_config)?)?;
+    stage.enable_site(&domain_config.domain)?;
+    stage.validate()?;
+    log_success("   ✓ Site staged and validated");
     Ok(())
 }
+
+/// Write the index page and error pages of the default site, then those of the
+/// domains whose config went live. Called after the commit so that a rejected
+/// domain or a failed run leaves the web root untouched.
+fn install_pages(results: &[DomainResult]) -> Result<()> {
+    ensure_error_pages_exist(None).map_err(|e| e.context("Failed to set up error pages"))?;
+    ensure_index_page_exists().map_err(|e| e.context("Failed to set up index page"))?;
+
+    let applied = results
+        .iter()
+        .filter(|result| matches!(result.status, DomainStatus::Applied | DomainStatus::FellBackToHttp { .. }));
+    for result in applied {
+        ensure_error_pages_exist(Some(&result.domain)).map_err(|e| e.context("Failed to set up error pages"))?;
+    }
+    Ok(())
+}
+
+fn print_summary(results: &[DomainResult]) {
+    if results.is_empty() {
+        return;
+    }
+
+    log_step("\n> Summary:");
+    for result in results {
+        match &result.status {
+            DomainStatus::Applied => log_success(&format!("   ✓ {} - applied", result.domain)),
+            DomainStatus::FellBackToHttp { reason, error } => log_warning(&format!(
+                "   ⚠️  {} - fell back to HTTP ({})",
+                result.domain,
+                match error {
+                    Some(e) => format!("{}: {}", reason, first_line(e.message())),
+                    None => reason.clone(),
+                }
+            )),
+            DomainStatus::Skipped { error } => log_warning(&format!(
+                "   ⚠️  {} - skipped, previous configuration kept ({})",
+                result.domain,
+                first_line(error.message())
+            )),
+            DomainStatus::Failed { error } => {
+                log_error(&format!("   ❌ {} - failed ({})", result.domain, first_line(error.message())))
+            }
+        }
+    }
+}
+
+/// nginx and certbot errors span several lines, the summary keeps the first meaningful one
+fn first_line(reason: &str) -> &str {
+    reason
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.ends_with(':'))
+        .unwrap_or(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"{
+        "defaults": { "email": "ops@example.com", "maxBodySize": "50M" },
+        "domains": [
+            { "domain": "app.example.com", "port": 3000, "ssl": true },
+            { "domain": "api.example.com", "port": 4000, "max_body_size": "1M" }
+        ]
+    }"#;
+
+    const YAML: &str = "
+defaults:
+  email: ops@example.com
+  maxBodySize: 50M
+domains:
+  - domain: app.example.com
+    port: 3000
+    ssl: true
+  - domain: api.example.com
+    port: 4000
+    max_body_size: 1M
+";
+
+    const TOML: &str = r#"
+[defaults]
+email = "ops@example.com"
+maxBodySize = "50M"
+
+[[domains]]
+domain = "app.example.com"
+port = 3000
+ssl = true
+
+[[domains]]
+domain = "api.example.com"
+port = 4000
+max_body_size = "1M"
+"#;
+
+    fn parse(content: &str, format: ConfigFormat) -> serde_json::Value {
+        serde_json::to_value(parse_config(content, format).unwrap()).unwrap()
+    }
+
+    fn parse_error(content: &str, format: ConfigFormat) -> String {
+        match parse_config(content, format) {
+            Err(Error::ConfigParse(message)) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn formats_parse_to_the_same_config() {
+        let json = parse(JSON, ConfigFormat::Json);
+        assert_eq!(parse(YAML, ConfigFormat::Yaml), json);
+        assert_eq!(parse(TOML, ConfigFormat::Toml), json);
+    }
+
+    #[test]
+    fn defaults_fill_the_fields_a_domain_does_not_set() {
+        let config = parse_config(YAML, ConfigFormat::Yaml).unwrap();
+        let (app, api) = (&config.domains[0], &config.domains[1]);
+
+        assert_eq!(app.email.as_deref(), Some("ops@example.com"));
+        assert_eq!(app.max_body_size, "50M");
+        assert_eq!(api.max_body_size, "1M");
+        assert_eq!(api.host, "localhost");
+        assert!(!api.ssl);
+    }
+
+    #[test]
+    fn errors_name_the_format() {
+        assert!(parse_error("domains: [", ConfigFormat::Yaml).starts_with("Invalid YAML config: "));
+        assert!(parse_error("domains = [", ConfigFormat::Toml).starts_with("Invalid TOML config: "));
+        assert!(parse_error("{", ConfigFormat::Json).starts_with("Invalid JSON config: "));
+    }
+
+    #[test]
+    fn unknown_fields_suggest_the_closest_one() {
+        let message = parse_error("domains:\n  - domain: a.com\n    max_body_sise: 1M\n", ConfigFormat::Yaml);
+        assert!(message.contains("did you mean `max_body_size`?"), "{}", message);
+
+        let message = parse_error("defaults:\n  emial: ops@example.com\n", ConfigFormat::Yaml);
+        assert!(message.contains("defaults."), "{}", message);
+        assert!(message.contains("did you mean `email`?"), "{}", message);
+
+        let message = parse_error("domains:\n  - domain: a.com\n    nothing_alike: 1\n", ConfigFormat::Yaml);
+        assert!(!message.contains("did you mean"), "{}", message);
+    }
+
+    #[test]
+    fn format_follows_the_extension_or_the_name() {
+        assert!(matches!(ConfigFormat::from_path("sites.yml"), Some(ConfigFormat::Yaml)));
+        assert!(matches!(ConfigFormat::from_path("conf/sites.YAML"), Some(ConfigFormat::Yaml)));
+        assert!(matches!(ConfigFormat::from_path("sites.toml"), Some(ConfigFormat::Toml)));
+        assert!(matches!(ConfigFormat::from_path("sites.json"), Some(ConfigFormat::Json)));
+        assert!(ConfigFormat::from_path("sites.conf").is_none());
+        assert!(ConfigFormat::from_path("sites").is_none());
+
+        assert!(matches!(ConfigFormat::parse("TOML"), Some(ConfigFormat::Toml)));
+        assert!(ConfigFormat::parse("ini").is_none());
+    }
+
+    fn write(dir: &Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn names(config: &Config) -> Vec<&str> {
+        config.domains.iter().map(|d| d.domain.as_str()).collect()
+    }
+
+    #[test]
+    fn includes_are_read_depth_first_and_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "include: [teams/*.yaml, shared.json, none/*.toml]\ndomains:\n  - domain: main.com\n    port: 1\n",
+        );
+        write(dir.path(), "teams/b.yaml", "domains:\n  - domain: b.com\n    port: 3\n");
+        write(dir.path(), "teams/a.yaml", "include: [../main.yaml]\ndomains:\n  - domain: a.com\n    port: 2\n");
+        write(dir.path(), "shared.json", r#"{ "include": ["teams/a.yaml"], "domains": [] }"#);
+
+        let config = read_config(&main, None).unwrap();
+        assert_eq!(names(&config), ["main.com", "a.com", "b.com"]);
+        assert!(config.include.is_empty());
+    }
+
+    #[test]
+    fn included_domains_inherit_and_override_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.toml",
+            "include = [\"team.yaml\"]\n[defaults]\nemail = \"ops@example.com\"\nhost = \"10.0.0.1\"\n",
+        );
+        write(
+            dir.path(),
+            "team.yaml",
+            "defaults:\n  host: 10.0.0.2\ndomains:\n  - domain: a.com\n    port: 1\n  - domain: b.com\n    port: 2\n    email: b@example.com\n",
+        );
+
+        let config = read_config(&main, None).unwrap();
+        let (a, b) = (&config.domains[0], &config.domains[1]);
+        assert_eq!((a.email.as_deref(), a.host.as_str()), (Some("ops@example.com"), "10.0.0.2"));
+        assert_eq!((b.email.as_deref(), b.host.as_str()), (Some("b@example.com"), "10.0.0.2"));
+    }
+
+    #[test]
+    fn a_missing_plain_include_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "main.yaml", "include: [missing.yaml]\n");
+
+        match read_config(&main, None) {
+            Err(Error::ConfigParse(message)) => assert!(message.contains("does not exist"), "{}", message),
+            other => panic!("expected a missing include error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_domain_defined_in_two_files_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "main.yaml", "include: [other.yaml]\ndomains:\n  - domain: a.com\n    port: 1\n");
+        let other = write(dir.path(), "other.yaml", "domains:\n  - domain: A.com\n    port: 2\n");
+
+        match read_config(&main, None) {
+            Err(Error::InvalidConfig(message)) => {
+                assert!(message.contains("defined in several config files"), "{}", message);
+                assert!(message.contains(&format!("'a.com' is defined in {} and {}", main, other)), "{}", message);
+            }
+            other => panic!("expected a duplicate domain error, got {:?}", other),
+        }
+    }
+}
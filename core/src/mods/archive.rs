@@ -0,0 +1,93 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+use crate::mods::backup::Compression;
+use crate::mods::error::{Error, Result};
+
+/// Pack the content of a directory into a compressed tar archive. `first` is
+/// written before the other entries, so that `read_entry` finds it quickly.
+/// Symlinks are stored as symlinks, with modes and ownership.
+pub fn pack(dir: &Path, archive: &Path, compression: Compression, first: &str) -> Result<()> {
+    let file = File::create(archive).map_err(Error::io(&format!("Failed to create {}", archive.display())))?;
+    let finished = match compression {
+        Compression::Gzip => {
+            let mut builder = tar::Builder::new(GzEncoder::new(file, flate2::Compression::default()));
+            append_entries(&mut builder, dir, first)?;
+            builder.into_inner().and_then(|encoder| encoder.finish()).map(drop)
+        }
+        Compression::Zstd => {
+            let encoder = zstd::Encoder::new(file, 0).map_err(Error::io("Failed to start zstd compression"))?;
+            let mut builder = tar::Builder::new(encoder);
+            append_entries(&mut builder, dir, first)?;
+            builder.into_inner().and_then(|encoder| encoder.finish()).map(drop)
+        }
+        Compression::None => {
+            return Err(Error::Other("An uncompressed backup is a plain directory".to_string()));
+        }
+    };
+    finished.map_err(Error::io(&format!("Failed to write {}", archive.display())))
+}
+
+fn append_entries<W: Write>(builder: &mut tar::Builder<W>, dir: &Path, first: &str) -> Result<()> {
+    builder.follow_symlinks(false);
+
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(Error::io(&format!("Failed to read {}", dir.display())))? {
+        names.push(entry.map_err(Error::io("Failed to read entry"))?.file_name());
+    }
+    names.sort_by_key(|name| (name != first, name.clone()));
+
+    for name in names {
+        let path = dir.join(&name);
+        let appended = if path.is_dir() && !path.is_symlink() {
+            builder.append_dir_all(&name, &path)
+        } else {
+            builder.append_path_with_name(&path, &name)
+        };
+        appended.map_err(Error::io(&format!("Failed to archive {}", path.display())))?;
+    }
+    Ok(())
+}
+
+/// Extract an archive made by `pack` into a directory, restoring modes and,
+/// when running as root, ownership
+pub fn unpack(archive: &Path, dir: &Path, compression: Compression) -> Result<()> {
+    let mut archive_reader = tar::Archive::new(reader(archive, compression)?);
+    archive_reader.set_preserve_permissions(true);
+    // SAFETY: geteuid() has no memory safety requirements
+    archive_reader.set_preserve_ownerships(unsafe { libc::geteuid() } == 0);
+    archive_reader.set_overwrite(true);
+    archive_reader
+        .unpack(dir)
+        .map_err(Error::io(&format!("Failed to extract {}", archive.display())))
+}
+
+/// Content of a top-level file of an archive, `None` when it is missing
+pub fn read_entry(archive: &Path, compression: Compression, name: &str) -> Result<Option<String>> {
+    let context = format!("Failed to read {}", archive.display());
+    let mut archive_reader = tar::Archive::new(reader(archive, compression)?);
+
+    for entry in archive_reader.entries().map_err(Error::io(&context))? {
+        let mut entry = entry.map_err(Error::io(&context))?;
+        let is_match = entry.path().map_err(Error::io(&context))?.as_os_str() == name;
+        if is_match {
+            let mut content = String::new();
+            entry.read_to_string(&mut content).map_err(Error::io(&context))?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+fn reader(archive: &Path, compression: Compression) -> Result<Box<dyn Read>> {
+    let context = format!("Failed to read {}", archive.display());
+    let file = BufReader::new(File::open(archive).map_err(Error::io(&context))?);
+    match compression {
+        Compression::Gzip => Ok(Box::new(GzDecoder::new(file))),
+        Compression::Zstd => Ok(Box::new(zstd::Decoder::with_buffer(file).map_err(Error::io(&context))?)),
+        Compression::None => Err(Error::Other(format!("{} is not an archive", archive.display()))),
+    }
+}
@@ -1,117 +1,1060 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tempfile::TempDir;
+use ulid::{Generator, Ulid};
 
-use crate::mods::constants::{BACKUP_DIR, NGINX_SITES_AVAILABLE, NGINX_SITES_ENABLED};
-use crate::mods::logger::log_success;
+use crate::mods::archive::{pack, read_entry, unpack};
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::{layout, Layout};
+use crate::mods::logger::{log_info, log_step, log_success, log_warning};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
 
-pub fn create_backup() -> Result<(), String> {
-    // Créer le répertoire de backup s'il n'existe pas
-    if !Path::new(BACKUP_DIR).exists() {
-        fs::create_dir_all(BACKUP_DIR)
-            .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+/// File describing a backup, at its root
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Prefix of the backup identifiers, followed by a ULID, or by the local
+/// creation time for backups of older versions
+const BACKUP_PREFIX: &str = "backup_";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// Reference to the most recent backup
+const LATEST: &str = "latest";
+
+/// Words labels cannot take: `live` stands for the live configuration in `backup diff`
+const RESERVED_LABELS: &[&str] = &[LATEST, "live"];
+
+/// ULIDs of this process increase even within a millisecond
+static ULIDS: Mutex<Generator> = Mutex::new(Generator::new());
+
+/// Compression of a backup: a plain directory, or a tar archive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    /// `.tar.gz`
+    Gzip,
+    /// `.tar.zst`
+    Zstd,
+}
+
+impl Compression {
+    /// Parse a compression name as written in the settings or the environment
+    pub fn parse(value: &str) -> Option<Compression> {
+        match value {
+            "none" => Some(Compression::None),
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
     }
 
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let backup_path = format!("{}/backup_{}", BACKUP_DIR, timestamp);
-    
-    fs::create_dir_all(&backup_path)
-        .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
 
-    // Copier sites-available
-    copy_directory(NGINX_SITES_AVAILABLE, &format!("{}/sites-available", backup_path))?;
-    
-    // Copier sites-enabled (symlinks)
-    copy_directory(NGINX_SITES_ENABLED, &format!("{}/sites-enabled", backup_path))?;
+    /// Suffix of the archive name, empty for a directory
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".tar.gz",
+            Compression::Zstd => ".tar.zst",
+        }
+    }
+}
 
-    log_success(&format!("   ✓ Backup created: {}", backup_path));
-    Ok(())
+/// `[backup]` section of the settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSettings {
+    /// Number of most recent backups kept by pruning
+    pub keep_last: u32,
+    /// Number of days for which the last backup of the day is kept
+    pub keep_daily: u32,
+    pub compression: Compression,
+    /// Prune after every successful apply
+    pub auto_prune: bool,
 }
 
-pub fn copy_directory(src: &str, dst: &str) -> Result<(), String> {
-    fs::create_dir_all(dst).map_err(|e| format!("Failed to create directory: {}", e))?;
-    
-    for entry in fs::read_dir(src).map_err(|e| format!("Failed to read directory: {}", e))? {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let file_name = entry.file_name();
-        let src_path = entry.path();
-        let dst_path = Path::new(dst).join(&file_name);
-        
-        if src_path.is_file() {
-            fs::copy(&src_path, &dst_path)
-                .map_err(|e| format!("Failed to copy file: {}", e))?;
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            keep_last: 10,
+            keep_daily: 7,
+            compression: Compression::None,
+            auto_prune: true,
         }
     }
-    
-    Ok(())
 }
 
-pub fn restore_latest_backup() -> Result<(), String> {
+/// Something xynginc writes, saved by every backup
+pub struct BackupSource {
+    /// Name of the entry inside a backup
+    pub name: &'static str,
+    pub path: String,
+    /// Whether a restore replaces the whole directory instead of overwriting files
+    pub replace_on_restore: bool,
+}
+
+/// Contents of `manifest.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// RFC 3339 creation time
+    pub created_at: String,
+    pub xynginc_version: String,
+    /// Command that took the backup (e.g. "apply", "remove")
+    pub trigger: String,
+    /// Name given with `backup create --label`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub layout: Layout,
+    /// Live location of each saved entry
+    pub sources: Vec<ManifestSource>,
+    /// Every saved file, directory and symlink, relative to the backup
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSource {
+    pub name: String,
+    /// Path as seen by nginx, without the root prefix
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub kind: FileKind,
+    /// Permission bits, written in octal (e.g. "0644")
+    #[serde(with = "octal_mode")]
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// SHA-256 of the content of a regular file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Target of a symlink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+mod octal_mode {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04o}", mode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let mode = String::deserialize(deserializer)?;
+        u32::from_str_radix(&mode, 8).map_err(|_| D::Error::custom(format!("invalid mode '{}'", mode)))
+    }
+}
+
+/// Everything a backup saves: the site directories of the layout, nginx.conf
+/// (rewritten by apply), the index page and the error pages
+pub fn backup_sources() -> Vec<BackupSource> {
+    let paths = paths();
+    let mut sources: Vec<BackupSource> = layout()
+        .site_dirs()
+        .into_iter()
+        .map(|dir| BackupSource {
+            name: dir.name,
+            path: dir.path,
+            replace_on_restore: dir.replace_on_restore,
+        })
+        .collect();
+
+    sources.extend([
+        BackupSource {
+            name: "nginx.conf",
+            path: paths.nginx_conf.clone(),
+            replace_on_restore: true,
+        },
+        BackupSource {
+            name: "index.html",
+            path: format!("{}/index.html", paths.web_root),
+            replace_on_restore: true,
+        },
+        BackupSource {
+            name: "errors",
+            path: format!("{}/errors", paths.web_root),
+            replace_on_restore: false,
+        },
+    ]);
+    sources
+}
+
+/// Save everything xynginc writes to a new backup, with its manifest,
+/// compressed as set in the settings. `trigger` names the command taking the
+/// backup. Returns the backup identifier.
+pub fn create_backup(trigger: &str) -> Result<String> {
+    create_compressed_backup(trigger, paths().backup.compression, None)
+}
+
+/// `create_backup` with an explicit compression and an optional label
+pub fn create_compressed_backup(trigger: &str, compression: Compression, label: Option<&str>) -> Result<String> {
+    if let Some(label) = label {
+        check_label(label)?;
+    }
+
+    // Create the backup directory on first use
+    if !Path::new(&paths().backup_dir).exists() {
+        fs::create_dir_all(&paths().backup_dir)
+            .map_err(Error::io("Failed to create backup directory"))?;
+    }
+
+    // The ULID carries the creation time; its random part avoids collisions
+    // between two commands run within the same millisecond. create_dir fails
+    // when the directory already exists, another ULID is then tried.
+    let now = Local::now();
+    let (backup, backup_path) = loop {
+        let ulid = ULIDS
+            .lock()
+            .ok()
+            .and_then(|mut generator| generator.generate_from_datetime(SystemTime::from(now)).ok())
+            .unwrap_or_else(|| Ulid::from_datetime(SystemTime::from(now)));
+        let backup = format!("{}{}", BACKUP_PREFIX, ulid);
+        let backup_path = format!("{}/{}", paths().backup_dir, backup);
+        if locate_backup(&backup).is_ok() {
+            continue;
+        }
+        match fs::create_dir(&backup_path) {
+            Ok(()) => break (backup, backup_path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::io("Failed to create backup directory")(e)),
+        }
+    };
+
+    // Copy the site directories, nginx.conf and the pages served by default
+    let mut sources = vec![];
+    for source in backup_sources() {
+        if Path::new(&source.path).symlink_metadata().is_err() {
+            continue;
+        }
+        copy_entry(Path::new(&source.path), &Path::new(&backup_path).join(source.name))?;
+        sources.push(ManifestSource {
+            name: source.name.to_string(),
+            path: paths().unrooted(&source.path).to_string(),
+        });
+    }
+
+    let mut files = vec![];
+    for source in &sources {
+        collect_files(Path::new(&backup_path), &source.name, &mut files)?;
+    }
+
+    let manifest = BackupManifest {
+        created_at: now.to_rfc3339(),
+        xynginc_version: env!("CARGO_PKG_VERSION").to_string(),
+        trigger: trigger.to_string(),
+        label: label.map(str::to_string),
+        layout: layout(),
+        sources,
+        files,
+    };
+    let content = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| Error::Other(format!("Failed to serialize backup manifest: {}", e)))?;
+    fs::write(format!("{}/{}", backup_path, MANIFEST_FILE), content)
+        .map_err(Error::io("Failed to write backup manifest"))?;
+
+    // The archive is written under a hidden name then renamed, never visible half written
+    let location = if compression == Compression::None {
+        backup_path
+    } else {
+        let archive = format!("{}{}", backup_path, compression.extension());
+        let partial = format!("{}/.{}{}.partial", paths().backup_dir, backup, compression.extension());
+        pack(Path::new(&backup_path), Path::new(&partial), compression, MANIFEST_FILE)?;
+        fs::rename(&partial, &archive).map_err(Error::io(&format!("Failed to create {}", archive)))?;
+        fs::remove_dir_all(&backup_path).map_err(Error::io(&format!("Failed to remove {}", backup_path)))?;
+        archive
+    };
+
+    log_success(&format!("   ✓ Backup created: {}", location));
+    Ok(backup)
+}
+
+/// Manifest of a backup, `None` for the backups taken before manifests existed
+pub fn read_manifest(backup: &str) -> Result<Option<BackupManifest>> {
+    let (location, compression) = locate_backup(backup)?;
+    let content = if compression == Compression::None {
+        let manifest_path = format!("{}/{}", location, MANIFEST_FILE);
+        if !Path::new(&manifest_path).exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(&manifest_path).map_err(Error::io(&format!("Failed to read {}", manifest_path)))?
+    } else {
+        match read_entry(Path::new(&location), compression, MANIFEST_FILE)? {
+            Some(content) => content,
+            None => return Ok(None),
+        }
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| Error::BackupMissing(format!("Invalid backup manifest in {}: {}", location, e)))
+}
+
+/// Location of a backup and how it is stored
+fn locate_backup(backup: &str) -> Result<(String, Compression)> {
+    let missing = || Error::BackupMissing(format!("Backup not found: {}/{}", paths().backup_dir, backup));
+    if backup.is_empty() || backup.contains('/') || backup.starts_with('.') {
+        return Err(missing());
+    }
+
+    [Compression::None, Compression::Gzip, Compression::Zstd]
+        .into_iter()
+        .map(|compression| (format!("{}/{}{}", paths().backup_dir, backup, compression.extension()), compression))
+        .find(|(location, _)| Path::new(location).exists())
+        .ok_or_else(missing)
+}
+
+/// Identifier of a backup given on the command line: an identifier, a label
+/// (its most recent backup) or "latest", followed by `~N` to go N backups back
+pub fn resolve_backup(reference: &str) -> Result<String> {
+    let (base, back) = match reference.rsplit_once('~') {
+        Some((base, back)) => {
+            let back = back.parse::<usize>().map_err(|_| {
+                Error::BackupMissing(format!("Invalid backup reference '{}': expected <backup>~N", reference))
+            })?;
+            (base, back)
+        }
+        None => (reference, 0),
+    };
+
+    if back == 0 && backup_time(base).is_some() {
+        locate_backup(base)?;
+        return Ok(base.to_string());
+    }
+
     let backups = list_backups()?;
-    
     if backups.is_empty() {
-        return Err("No backups available".to_string());
+        return Err(Error::BackupMissing("No backups available".to_string()));
+    }
+    let index = if base == LATEST {
+        Some(0)
+    } else {
+        backups.iter().position(|backup| backup == base).or_else(|| {
+            backups
+                .iter()
+                .position(|backup| read_manifest(backup).ok().flatten().and_then(|m| m.label).as_deref() == Some(base))
+        })
+    };
+    let index = index.ok_or_else(|| {
+        Error::BackupMissing(format!("Backup not found: no backup has the identifier or label '{}'", base))
+    })?;
+
+    backups.get(index + back).cloned().ok_or_else(|| {
+        Error::BackupMissing(format!(
+            "Backup not found: {} only has {} older backup(s)",
+            base,
+            backups.len() - index - 1
+        ))
+    })
+}
+
+/// Check a label given to a backup: it must not be mistaken for an identifier
+/// or a reference
+fn check_label(label: &str) -> Result<()> {
+    let is_valid = label.len() <= 64
+        && label.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !is_valid {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid backup label '{}': use up to 64 letters, digits, '.', '_' and '-', starting with a letter or a digit",
+            label
+        )));
+    }
+    if RESERVED_LABELS.contains(&label) || backup_time(label).is_some() {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid backup label '{}': it is reserved or would be read as a backup identifier",
+            label
+        )));
+    }
+    Ok(())
+}
+
+/// Copy a file, symlink or directory tree. Symlinks are recreated as symlinks,
+/// and modes and ownership are kept (ownership only when running as root).
+/// An existing destination is replaced, except directories which are merged.
+pub fn copy_entry(src: &Path, dst: &Path) -> Result<()> {
+    let metadata = src
+        .symlink_metadata()
+        .map_err(Error::io(&format!("Failed to read {}", src.display())))?;
+    let file_type = metadata.file_type();
+
+    if let Ok(existing) = dst.symlink_metadata()
+        && !(existing.is_dir() && file_type.is_dir())
+    {
+        let removed = if existing.is_dir() { fs::remove_dir_all(dst) } else { fs::remove_file(dst) };
+        removed.map_err(Error::io(&format!("Failed to replace {}", dst.display())))?;
+    }
+
+    if file_type.is_symlink() {
+        let target = fs::read_link(src).map_err(Error::io(&format!("Failed to read link {}", src.display())))?;
+        symlink(&target, dst).map_err(Error::io(&format!("Failed to create symlink {}", dst.display())))?;
+    } else if file_type.is_dir() {
+        if !dst.exists() {
+            fs::create_dir(dst).map_err(Error::io(&format!("Failed to create {}", dst.display())))?;
+        }
+        let mut entries = vec![];
+        for entry in fs::read_dir(src).map_err(Error::io(&format!("Failed to read {}", src.display())))? {
+            entries.push(entry.map_err(Error::io("Failed to read entry"))?.file_name());
+        }
+        entries.sort();
+        for name in entries {
+            copy_entry(&src.join(&name), &dst.join(&name))?;
+        }
+        // After the content, so that a read-only directory can still be filled
+        fs::set_permissions(dst, metadata.permissions())
+            .map_err(Error::io(&format!("Failed to set mode of {}", dst.display())))?;
+    } else if file_type.is_file() {
+        // fs::copy keeps the permission bits
+        fs::copy(src, dst).map_err(Error::io(&format!("Failed to copy {}", src.display())))?;
+    } else {
+        // Sockets, FIFOs and devices have no place in a config tree
+        return Ok(());
+    }
+
+    copy_ownership(&metadata, dst)
+}
+
+fn copy_ownership(metadata: &fs::Metadata, dst: &Path) -> Result<()> {
+    // SAFETY: geteuid() has no memory safety requirements
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+    lchown(dst, Some(metadata.uid()), Some(metadata.gid()))
+        .map_err(Error::io(&format!("Failed to set owner of {}", dst.display())))
+}
+
+/// Describe `relative` (inside `base`) and everything under it for the manifest
+fn collect_files(base: &Path, relative: &str, files: &mut Vec<ManifestFile>) -> Result<()> {
+    let path = base.join(relative);
+    let metadata = path
+        .symlink_metadata()
+        .map_err(Error::io(&format!("Failed to read {}", path.display())))?;
+    let file_type = metadata.file_type();
+
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else {
+        FileKind::File
+    };
+    files.push(ManifestFile {
+        path: relative.to_string(),
+        kind,
+        mode: metadata.permissions().mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        sha256: match kind {
+            FileKind::File => Some(file_checksum(&path).map_err(Error::io(&format!("Failed to read {}", path.display())))?),
+            _ => None,
+        },
+        target: match kind {
+            FileKind::Symlink => fs::read_link(&path).ok().map(|target| target.to_string_lossy().to_string()),
+            _ => None,
+        },
+    });
+
+    if kind == FileKind::Dir {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&path).map_err(Error::io(&format!("Failed to read {}", path.display())))? {
+            entries.push(entry.map_err(Error::io("Failed to read entry"))?.file_name());
+        }
+        entries.sort();
+        for name in entries {
+            collect_files(base, &format!("{}/{}", relative, name.to_string_lossy()), files)?;
+        }
     }
-    
-    let latest = &backups[0];
-    restore_backup(latest)?;
-    
     Ok(())
 }
 
-pub fn list_backups() -> Result<Vec<String>, String> {
-    if !Path::new(BACKUP_DIR).exists() {
+/// SHA-256 of a file as a hex string
+pub fn file_checksum(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Backup identifiers, most recent first. Other entries of the backup
+/// directory (notes, archives being written...) are left out.
+pub fn list_backups() -> Result<Vec<String>> {
+    if !Path::new(&paths().backup_dir).exists() {
         return Ok(vec![]);
     }
 
     let mut backups = vec![];
-    
-    for entry in fs::read_dir(BACKUP_DIR).map_err(|e| format!("Failed to read backups: {}", e))? {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let name = entry.file_name();
-        backups.push(name.to_string_lossy().to_string());
+
+    for entry in fs::read_dir(&paths().backup_dir).map_err(Error::io("Failed to read backups"))? {
+        let entry = entry.map_err(Error::io("Failed to read entry"))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+        // A backup is a directory, or an archive of the same name
+        let id = match [Compression::Gzip, Compression::Zstd]
+            .iter()
+            .find_map(|compression| name.strip_suffix(compression.extension()))
+        {
+            Some(id) if !is_dir => id,
+            None if is_dir => &name,
+            _ => continue,
+        };
+        if let Some(time) = backup_time(id) {
+            backups.push((time, id.to_string()));
+        }
     }
-    
+
+    // Most recent first
     backups.sort();
-    backups.reverse(); // Plus récent en premier
-    
-    Ok(backups)
+    backups.reverse();
+
+    Ok(backups.into_iter().map(|(_, backup)| backup).collect())
 }
 
-pub fn restore_backup(backup_id: &str) -> Result<(), String> {
-    use crate::mods::logger::log_step;
-    
-    let backup_path = if backup_id == "latest" {
-        let backups = list_backups()?;
-        if backups.is_empty() {
-            return Err("No backups available".to_string());
+/// A backup readable as a directory. A compressed backup is extracted to a
+/// private temporary directory, removed when this is dropped.
+pub struct OpenedBackup {
+    pub id: String,
+    /// Where the backup is stored (directory or archive)
+    pub location: String,
+    /// Directory holding the saved entries
+    pub path: PathBuf,
+    /// Site layout of the saved directories
+    pub layout: Layout,
+    /// Directory of an extracted archive, removed on drop
+    _extracted: Option<TempDir>,
+}
+
+/// Open a backup ("latest" for the most recent one) for reading
+pub fn open_backup(backup_id: &str) -> Result<OpenedBackup> {
+    let id = resolve_backup(backup_id)?;
+    let (location, compression) = locate_backup(&id)?;
+    let manifest = read_manifest(&id)?;
+
+    let (path, extracted) = if compression == Compression::None {
+        (PathBuf::from(&location), None)
+    } else {
+        // Extract inside a new directory only we can enter (mode 0700, random
+        // name), so that `backup diff` also works without being root. The
+        // archive sets the mode of the directory it is unpacked to.
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("xynginc-{}-", id))
+            .permissions(fs::Permissions::from_mode(0o700))
+            .tempdir()
+            .map_err(Error::io("Failed to create a temporary directory"))?;
+        let path = dir.path().join("backup");
+        unpack(Path::new(&location), &path, compression)?;
+        (path, Some(dir))
+    };
+
+    // Backups without a manifest only hold the site directories
+    let layout = match manifest {
+        Some(manifest) => manifest.layout,
+        None if path.join("conf.d").is_dir() => Layout::ConfD,
+        None => Layout::SitesEnabled,
+    };
+
+    Ok(OpenedBackup {
+        id,
+        location,
+        path,
+        layout,
+        _extracted: extracted,
+    })
+}
+
+/// A backup as listed by `xynginc backup list`
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    pub id: String,
+    pub path: String,
+    pub compression: Compression,
+    /// Size on disk in bytes
+    pub size: u64,
+    /// Unknown for backups taken before manifests existed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl CommandReport for BackupInfo {}
+
+#[derive(Debug, Serialize)]
+pub struct BackupList {
+    pub backup_dir: String,
+    pub backups: Vec<BackupInfo>,
+}
+
+impl CommandReport for BackupList {}
+
+/// A backup with its manifest, for `xynginc backup show`
+#[derive(Debug, Serialize)]
+pub struct BackupDetails {
+    #[serde(flatten)]
+    pub info: BackupInfo,
+    pub manifest: Option<BackupManifest>,
+}
+
+impl CommandReport for BackupDetails {}
+
+#[derive(Debug, Serialize)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    pub keep_last: u32,
+    pub keep_daily: u32,
+    pub kept: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl CommandReport for PruneReport {}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteReport {
+    pub deleted: Vec<String>,
+}
+
+impl CommandReport for DeleteReport {}
+
+/// Location, size and origin of a backup
+pub fn backup_info(backup: &str) -> Result<BackupInfo> {
+    let (location, compression) = locate_backup(backup)?;
+    let manifest = read_manifest(backup)?;
+    Ok(BackupInfo {
+        id: backup.to_string(),
+        size: disk_usage(Path::new(&location)),
+        path: location,
+        compression,
+        created_at: manifest.as_ref().map(|m| m.created_at.clone()),
+        trigger: manifest.as_ref().map(|m| m.trigger.clone()),
+        label: manifest.and_then(|m| m.label),
+    })
+}
+
+/// List the backups, most recent first
+pub fn show_backups() -> Result<BackupList> {
+    log_step(&format!("Backups in {}:\n", paths().backup_dir));
+
+    let mut backups = vec![];
+    for backup in list_backups()? {
+        let info = backup_info(&backup)?;
+        log_info(&format!(
+            "   {:<33} {:>9}  {:<5} {:<14} {}",
+            info.id,
+            human_size(info.size),
+            info.compression.name(),
+            info.trigger.as_deref().unwrap_or("-"),
+            info.label.as_deref().unwrap_or("")
+        ));
+        backups.push(info);
+    }
+
+    if backups.is_empty() {
+        log_info("   (no backups)");
+    }
+
+    Ok(BackupList {
+        backup_dir: paths().backup_dir.clone(),
+        backups,
+    })
+}
+
+/// Show a backup and the files it holds
+pub fn show_backup(backup_id: &str) -> Result<BackupDetails> {
+    let backup = resolve_backup(backup_id)?;
+    let info = backup_info(&backup)?;
+    let manifest = read_manifest(&backup)?;
+
+    log_step(&format!("Backup {}\n", info.id));
+    log_info(&format!("   Location:    {}", info.path));
+    log_info(&format!("   Size:        {} ({})", human_size(info.size), info.compression.name()));
+
+    match &manifest {
+        Some(manifest) => {
+            log_info(&format!("   Created:     {}", manifest.created_at));
+            log_info(&format!("   Trigger:     {}", manifest.trigger));
+            if let Some(label) = &manifest.label {
+                log_info(&format!("   Label:       {}", label));
+            }
+            log_info(&format!("   Version:     {}", manifest.xynginc_version));
+            log_info(&format!("   Layout:      {}", manifest.layout.name()));
+            log_info("\n   Files:");
+            for file in &manifest.files {
+                let target = file.target.as_deref().map(|t| format!(" -> {}", t)).unwrap_or_default();
+                log_info(&format!(
+                    "   {:<7} {:04o} {:>5}:{:<5} {}{}",
+                    format!("{:?}", file.kind).to_lowercase(),
+                    file.mode,
+                    file.uid,
+                    file.gid,
+                    file.path,
+                    target
+                ));
+            }
+        }
+        None => log_info("   No manifest: taken by an older version of xynginc"),
+    }
+
+    Ok(BackupDetails { info, manifest })
+}
+
+/// Take a backup on demand
+pub fn backup_now(compression: Option<Compression>, label: Option<&str>) -> Result<BackupInfo> {
+    log_step("> Creating backup...");
+    let compression = compression.unwrap_or(paths().backup.compression);
+    let backup = create_compressed_backup("backup create", compression, label)?;
+    backup_info(&backup)
+}
+
+/// Delete backups; nothing is deleted unless they all exist
+pub fn delete_backups(backup_ids: &[String]) -> Result<DeleteReport> {
+    let backups = backup_ids
+        .iter()
+        .map(|id| resolve_backup(id))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut deleted = vec![];
+    for backup in backups {
+        if deleted.contains(&backup) {
+            continue;
         }
-        format!("{}/{}", BACKUP_DIR, backups[0])
+        remove_backup(&backup)?;
+        log_success(&format!("   🗑️  Deleted {}", backup));
+        deleted.push(backup);
+    }
+    Ok(DeleteReport { deleted })
+}
+
+fn remove_backup(backup: &str) -> Result<()> {
+    let (location, compression) = locate_backup(backup)?;
+    let removed = if compression == Compression::None {
+        fs::remove_dir_all(&location)
     } else {
-        format!("{}/{}", BACKUP_DIR, backup_id)
+        fs::remove_file(&location)
     };
+    removed.map_err(Error::io(&format!("Failed to delete {}", location)))
+}
+
+/// Delete the backups the retention rules do not keep. Unset rules come from
+/// the settings. Entries that are not backups are never deleted.
+pub fn prune_backups(keep_last: Option<u32>, keep_daily: Option<u32>, dry_run: bool) -> Result<PruneReport> {
+    let keep_last = keep_last.unwrap_or(paths().backup.keep_last);
+    let keep_daily = keep_daily.unwrap_or(paths().backup.keep_daily);
+    log_step(&format!(
+        "> Pruning backups (keeping the last {} and one a day for {} day(s))...",
+        keep_last, keep_daily
+    ));
+
+    let backups: Vec<(String, NaiveDateTime)> = list_backups()?
+        .into_iter()
+        .filter_map(|backup| {
+            let time = backup_time(&backup)?;
+            Some((backup, time))
+        })
+        .collect();
+    let today = chrono::Local::now().date_naive();
+    let kept = retained(&backups, keep_last, keep_daily, today);
 
-    if !Path::new(&backup_path).exists() {
-        return Err(format!("Backup not found: {}", backup_path));
+    let mut deleted = vec![];
+    for (backup, _) in &backups {
+        if kept.contains(backup) {
+            continue;
+        }
+        if dry_run {
+            log_info(&format!("   Would delete {}", backup));
+        } else {
+            remove_backup(backup)?;
+            log_info(&format!("   🗑️  Deleted {}", backup));
+        }
+        deleted.push(backup.clone());
     }
 
-    log_step(&format!("🔄 Restoring from backup: {}", backup_path));
+    if deleted.is_empty() {
+        log_success("   ✓ Nothing to prune");
+    } else if !dry_run {
+        log_success(&format!("   ✓ {} backup(s) deleted, {} kept", deleted.len(), kept.len()));
+    } else {
+        log_warning(&format!("   Dry run: {} backup(s) would be deleted", deleted.len()));
+    }
+
+    Ok(PruneReport {
+        dry_run,
+        keep_last,
+        keep_daily,
+        kept,
+        deleted,
+    })
+}
+
+/// Local creation time carried by a backup identifier, which orders the
+/// backups; `None` for a name that is not a backup identifier
+fn backup_time(backup: &str) -> Option<NaiveDateTime> {
+    let rest = backup.strip_prefix(BACKUP_PREFIX)?;
 
-    // Supprimer les configs actuelles
-    for entry in fs::read_dir(NGINX_SITES_ENABLED).map_err(|e| format!("Failed to read sites-enabled: {}", e))? {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let path = entry.path();
-        if path.is_file() || path.is_symlink() {
-            fs::remove_file(&path).map_err(|e| format!("Failed to remove file: {}", e))?;
+    if let Ok(ulid) = Ulid::from_string(rest)
+        && ulid.to_string() == rest
+    {
+        return Some(DateTime::<Local>::from(ulid.datetime()).naive_local());
+    }
+
+    // Previous versions named backups after the local time, to the second
+    NaiveDateTime::parse_from_str(rest, BACKUP_TIME_FORMAT)
+        .ok()
+        .filter(|time| time.format(BACKUP_TIME_FORMAT).to_string() == rest)
+}
+
+/// Backups kept among `backups` (most recent first): the `keep_last` most
+/// recent, the most recent of each of the last `keep_daily` days, and always
+/// the latest one, so that pruning never leaves nothing to restore
+fn retained(backups: &[(String, NaiveDateTime)], keep_last: u32, keep_daily: u32, today: NaiveDate) -> Vec<String> {
+    let mut kept_days: Vec<NaiveDate> = vec![];
+    let mut kept = vec![];
+
+    for (index, (backup, time)) in backups.iter().enumerate() {
+        let day = time.date();
+        let age = (today - day).num_days();
+        let is_daily = age >= 0 && age < i64::from(keep_daily) && !kept_days.contains(&day);
+        if index == 0 || index < keep_last as usize || is_daily {
+            kept.push(backup.clone());
+        }
+        if is_daily {
+            kept_days.push(day);
         }
     }
+    kept
+}
 
-    // Restaurer sites-available
-    copy_directory(&format!("{}/sites-available", backup_path), NGINX_SITES_AVAILABLE)?;
-    
-    // Restaurer sites-enabled
-    copy_directory(&format!("{}/sites-enabled", backup_path), NGINX_SITES_ENABLED)?;
+/// Bytes used by a file or a directory tree, symlinks not followed
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| disk_usage(&entry.path())).sum())
+        .unwrap_or(0)
+}
 
-    log_success("✓ Backup restored successfully");
-    
-    Ok(())
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    /// Backups named after their creation time, most recent first
+    fn backups(times: &[&str]) -> Vec<(String, NaiveDateTime)> {
+        times
+            .iter()
+            .map(|time| {
+                let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap();
+                (format!("{}{}", BACKUP_PREFIX, time.format(BACKUP_TIME_FORMAT)), time)
+            })
+            .collect()
+    }
+
+    fn names(backups: &[(String, NaiveDateTime)], indexes: &[usize]) -> Vec<String> {
+        indexes.iter().map(|&index| backups[index].0.clone()).collect()
+    }
+
+    fn day(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn retains_the_last_backups() {
+        let backups = backups(&["2026-10-17 12:00", "2026-10-17 11:00", "2026-10-16 12:00", "2026-10-10 12:00"]);
+        assert_eq!(retained(&backups, 2, 0, day("2026-10-17")), names(&backups, &[0, 1]));
+    }
+
+    #[test]
+    fn retains_the_most_recent_backup_of_each_day() {
+        let backups = backups(&[
+            "2026-10-17 12:00",
+            "2026-10-17 11:00",
+            "2026-10-16 18:00",
+            "2026-10-16 09:00",
+            "2026-10-14 12:00",
+            "2026-10-10 12:00",
+        ]);
+        assert_eq!(retained(&backups, 0, 4, day("2026-10-17")), names(&backups, &[0, 2, 4]));
+        // The two rules add up
+        assert_eq!(retained(&backups, 2, 2, day("2026-10-17")), names(&backups, &[0, 1, 2]));
+    }
+
+    #[test]
+    fn always_retains_the_latest_backup() {
+        let backups = backups(&["2026-10-01 12:00", "2026-09-30 12:00"]);
+        assert_eq!(retained(&backups, 0, 0, day("2026-10-17")), names(&backups, &[0]));
+        assert_eq!(retained(&backups, 0, 7, day("2026-10-17")), names(&backups, &[0]));
+        assert!(retained(&[], 0, 0, day("2026-10-17")).is_empty());
+    }
+
+    #[test]
+    fn reads_the_time_of_backup_identifiers() {
+        assert_eq!(
+            backup_time("backup_20261017_120000"),
+            NaiveDateTime::parse_from_str("2026-10-17 12:00", "%Y-%m-%d %H:%M").ok()
+        );
+        assert!(backup_time(&format!("{}{}", BACKUP_PREFIX, Ulid::new())).is_some());
+        for name in ["backup_20261017_120000_2", "backup_2026", "backup_", "notes.txt", "backup_latest"] {
+            assert_eq!(backup_time(name), None, "{}", name);
+        }
+    }
+
+    /// Empty the backup directory of the sandbox
+    fn clear_backups() {
+        let _ = fs::remove_dir_all(&paths().backup_dir);
+    }
+
+    #[test]
+    fn resolves_backup_references() {
+        let _sandbox = sandbox();
+        clear_backups();
+        assert!(matches!(resolve_backup("latest"), Err(Error::BackupMissing(_))));
+
+        let first = create_compressed_backup("test", Compression::None, Some("stable")).unwrap();
+        let second = create_compressed_backup("test", Compression::Gzip, None).unwrap();
+        let third = create_compressed_backup("test", Compression::None, Some("stable")).unwrap();
+
+        assert_eq!(resolve_backup("latest").unwrap(), third);
+        assert_eq!(resolve_backup("latest~1").unwrap(), second);
+        assert_eq!(resolve_backup("latest~2").unwrap(), first);
+        assert_eq!(resolve_backup(&second).unwrap(), second);
+        assert_eq!(resolve_backup(&format!("{}~1", second)).unwrap(), first);
+        // A label names its most recent backup, `~N` then goes back through all backups
+        assert_eq!(resolve_backup("stable").unwrap(), third);
+        assert_eq!(resolve_backup("stable~1").unwrap(), second);
+
+        for reference in ["latest~3", "latest~x", "unknown", "backup_20261017_120000"] {
+            assert!(matches!(resolve_backup(reference), Err(Error::BackupMissing(_))), "{}", reference);
+        }
+        clear_backups();
+    }
+
+    #[test]
+    fn rejects_labels_read_as_references() {
+        let _sandbox = sandbox();
+        clear_backups();
+        for label in ["live", "latest", "backup_20261017_120000", "stable~1", "-stable", ""] {
+            let result = create_compressed_backup("test", Compression::None, Some(label));
+            assert!(matches!(result, Err(Error::InvalidConfig(_))), "{}", label);
+        }
+        assert!(list_backups().unwrap().is_empty());
+    }
+
+    #[test]
+    fn opens_compressed_backups_in_a_private_directory() {
+        let _sandbox = sandbox();
+        clear_backups();
+        let id = create_compressed_backup("test", Compression::Zstd, None).unwrap();
+
+        let backup = open_backup(&id).unwrap();
+        let path = backup.path.clone();
+        assert!(path.join(MANIFEST_FILE).is_file());
+        assert!(path.join("nginx.conf").is_file());
+        let private = path.parent().unwrap().to_path_buf();
+        assert_eq!(fs::metadata(&private).unwrap().permissions().mode() & 0o777, 0o700);
+        drop(backup);
+        assert!(!private.exists());
+        clear_backups();
+    }
+
+    #[test]
+    fn manifests_record_modes_checksums_and_symlinks() {
+        let _sandbox = sandbox();
+        clear_backups();
+        let available = format!("{}/shop.com", paths().sites_available);
+        let enabled = format!("{}/shop.com", paths().sites_enabled);
+        fs::write(&available, "server {}\n").unwrap();
+        fs::set_permissions(&available, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink("/etc/nginx/sites-available/shop.com", &enabled).unwrap();
+
+        let id = create_compressed_backup("test", Compression::Gzip, None).unwrap();
+        let manifest = read_manifest(&id).unwrap().unwrap();
+        let entry = |path: &str| manifest.files.iter().find(|file| file.path == path).unwrap();
+
+        let file = entry("sites-available/shop.com");
+        assert_eq!((file.kind, file.mode), (FileKind::File, 0o640));
+        assert_eq!(file.sha256, Some(file_checksum(Path::new(&available)).unwrap()));
+        let link = entry("sites-enabled/shop.com");
+        assert_eq!(link.kind, FileKind::Symlink);
+        assert_eq!(link.target.as_deref(), Some("/etc/nginx/sites-available/shop.com"));
+        assert_eq!(entry("sites-enabled").kind, FileKind::Dir);
+
+        // The saved copy keeps them too
+        let backup = open_backup(&id).unwrap();
+        let saved = backup.path.join("sites-available/shop.com");
+        assert_eq!(fs::metadata(&saved).unwrap().permissions().mode() & 0o7777, 0o640);
+        let saved_link = backup.path.join("sites-enabled/shop.com");
+        assert_eq!(fs::read_link(saved_link).unwrap(), Path::new("/etc/nginx/sites-available/shop.com"));
+
+        fs::remove_file(&enabled).unwrap();
+        fs::remove_file(&available).unwrap();
+        clear_backups();
+    }
+
+    #[test]
+    fn copies_keep_modes_and_symlinks_and_replace_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("site"), "server {}\n").unwrap();
+        fs::set_permissions(src.join("site"), fs::Permissions::from_mode(0o600)).unwrap();
+        symlink("site", src.join("link")).unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o750)).unwrap();
+
+        // An existing file is replaced, an existing directory merged
+        let dst = dir.path().join("dst");
+        fs::create_dir(&dst).unwrap();
+        fs::write(dst.join("link"), "old").unwrap();
+        fs::write(dst.join("other"), "kept").unwrap();
+        copy_entry(&src, &dst).unwrap();
+
+        let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&dst), 0o750);
+        assert_eq!(mode(&dst.join("site")), 0o600);
+        assert_eq!(fs::read_to_string(dst.join("site")).unwrap(), "server {}\n");
+        assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("site"));
+        assert_eq!(fs::read_to_string(dst.join("other")).unwrap(), "kept");
+    }
 }
@@ -1,69 +1,108 @@
+use serde::Serialize;
 use std::path::Path;
 use std::process::Command;
 
-use crate::mods::constants::{BACKUP_DIR, NGINX_SITES_AVAILABLE, NGINX_SITES_ENABLED};
-use crate::mods::logger::{log_error, log_info, log_step, log_success};
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::{layout, Layout};
+use crate::mods::logger::{log_error, log_info, log_label, log_step, log_success};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
 
-pub fn check_requirements() -> Result<(), String> {
-    log_step("> Checking system requirements...\n");
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    /// Installed versions, `None` when the tool is missing
+    pub nginx: Option<String>,
+    pub certbot: Option<String>,
+    pub layout: Layout,
+    pub site_dirs: Vec<SiteDirCheck>,
+    pub backup_dir: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteDirCheck {
+    pub path: String,
+    pub exists: bool,
+}
+
+impl CommandReport for CheckReport {
+    fn failure(&self) -> Option<Error> {
+        let all_ok = self.nginx.is_some() && self.certbot.is_some() && self.site_dirs.iter().all(|dir| dir.exists);
+        if all_ok {
+            None
+        } else {
+            Some(Error::MissingRequirements(
+                "Some requirements are missing. Please install nginx and certbot.".to_string(),
+            ))
+        }
+    }
+}
 
-    let mut all_ok = true;
+pub fn check_requirements() -> Result<CheckReport> {
+    log_step("> Checking system requirements...\n");
 
     // Check nginx
-    print!("   nginx:   ");
-    match Command::new("nginx").arg("-v").output() {
+    log_label("   nginx:   ");
+    let nginx = match Command::new("nginx").arg("-v").output() {
         Ok(output) => {
-            let version = String::from_utf8_lossy(&output.stderr);
-            log_success(&format!("✓ {}", version.trim()));
+            let version = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            log_success(&format!("✓ {}", version));
+            Some(version)
         }
         Err(_) => {
             log_error("❌ Not installed");
-            all_ok = false;
+            None
         }
-    }
+    };
 
     // Check certbot
-    print!("   certbot: ");
-    match Command::new("certbot").arg("--version").output() {
+    log_label("   certbot: ");
+    let certbot = match Command::new("certbot").arg("--version").output() {
         Ok(output) => {
-            let version = String::from_utf8_lossy(&output.stdout);
-            log_success(&format!("✓ {}", version.trim()));
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            log_success(&format!("✓ {}", version));
+            Some(version)
         }
         Err(_) => {
             log_error("❌ Not installed");
-            all_ok = false;
+            None
         }
-    }
+    };
 
     // Check directories
-    print!("   nginx sites-available: ");
-    if Path::new(NGINX_SITES_AVAILABLE).exists() {
-        log_success(&format!("✓ {}", NGINX_SITES_AVAILABLE));
-    } else {
-        log_error("❌ Not found");
-        all_ok = false;
-    }
+    let layout = layout();
+    log_label("   site layout:           ");
+    log_success(layout.name());
 
-    print!("   nginx sites-enabled:   ");
-    if Path::new(NGINX_SITES_ENABLED).exists() {
-        log_success(&format!("✓ {}", NGINX_SITES_ENABLED));
-    } else {
-        log_error("❌ Not found");
-        all_ok = false;
+    let mut site_dirs = vec![];
+    for dir in layout.site_dirs() {
+        log_label(&format!("   nginx {:<16} ", format!("{}:", dir.name)));
+        let exists = Path::new(&dir.path).exists();
+        if exists {
+            log_success(&format!("✓ {}", dir.path));
+        } else {
+            log_error("❌ Not found");
+        }
+        site_dirs.push(SiteDirCheck { path: dir.path, exists });
     }
 
     // Check backup directory
-    print!("   backup directory:      ");
-    if Path::new(BACKUP_DIR).exists() {
-        log_success(&format!("✓ {}", BACKUP_DIR));
+    log_label("   backup directory:      ");
+    let backup_dir = Path::new(&paths().backup_dir).exists();
+    if backup_dir {
+        log_success(&format!("✓ {}", paths().backup_dir));
     } else {
-        log_info(&format!("it will be created: {}", BACKUP_DIR));
+        log_info(&format!("it will be created: {}", paths().backup_dir));
     }
 
-    if all_ok {
+    let report = CheckReport {
+        nginx,
+        certbot,
+        layout,
+        site_dirs,
+        backup_dir,
+    };
+    if report.failure().is_none() {
         log_success("\n✅ All requirements met!");
-        Ok(())
-    } else {
-        Err("Some requirements are missing. Please install nginx and certbot.".to_string())
     }
+    Ok(report)
 }
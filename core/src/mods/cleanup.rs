@@ -1,44 +1,44 @@
-use std::fs;
-use std::path::Path;
-use std::process::Command;
+use serde::Serialize;
 
-use crate::mods::constants::{NGINX_SITES_AVAILABLE, NGINX_SITES_ENABLED};
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::layout;
 use crate::mods::logger::{log_info, log_step, log_success, log_warning};
+use crate::mods::nginx::{live_config, run_nginx_test};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
 
-pub fn detect_broken_configs() -> Result<Vec<String>, String> {
+pub fn detect_broken_configs() -> Result<Vec<String>> {
     let mut broken = vec![];
 
     // Test nginx config
-    let output = Command::new("nginx")
-        .arg("-t")
-        .output()
-        .map_err(|e| format!("Failed to run nginx -t: {}", e))?;
+    let output = run_nginx_test(live_config())
+        .map_err(Error::io("Failed to run nginx -t"))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         
+        // nginx reports the paths it read, i.e. without the root prefix
+        let paths = paths();
+        let layout = layout();
+        let letsencrypt_live = format!("{}/", paths.unrooted(&paths.letsencrypt_live_dir));
+
         // Parser le output pour trouver les fichiers problématiques
         for line in stderr.lines() {
             // Chercher les patterns comme "cannot load certificate" ou "unknown directive"
-            if line.contains("in /etc/nginx/sites-enabled/") {
-                if let Some(start) = line.find("/etc/nginx/sites-enabled/") {
-                    if let Some(domain_end) = line[start + 25..].find(':') {
-                        let domain = &line[start + 25..start + 25 + domain_end];
-                        if !broken.contains(&domain.to_string()) {
-                            broken.push(domain.to_string());
-                        }
-                    }
+            if let Some(domain) = layout.site_in_error(line) {
+                if !broken.contains(&domain) {
+                    broken.push(domain);
                 }
             } else if line.contains("cannot load certificate") {
                 // Extraire le domaine du path du certificat
-                if let Some(start) = line.find("/etc/letsencrypt/live/") {
-                    if let Some(end) = line[start + 22..].find('/') {
-                        let domain = &line[start + 22..start + 22 + end];
-                        // Trouver la config correspondante
-                        let config_path = format!("{}/{}", NGINX_SITES_ENABLED, domain);
-                        if Path::new(&config_path).exists() && !broken.contains(&domain.to_string()) {
-                            broken.push(domain.to_string());
-                        }
+                if let Some(start) = line.find(&letsencrypt_live)
+                    && let Some(end) = line[start + letsencrypt_live.len()..].find('/')
+                {
+                    let start = start + letsencrypt_live.len();
+                    let domain = &line[start..start + end];
+                    // Trouver la config correspondante
+                    if layout.is_enabled(domain) && !broken.contains(&domain.to_string()) {
+                        broken.push(domain.to_string());
                     }
                 }
             }
@@ -48,14 +48,36 @@ pub fn detect_broken_configs() -> Result<Vec<String>, String> {
     Ok(broken)
 }
 
-pub fn clean_broken_configs(dry_run: bool) -> Result<(), String> {
+#[derive(Debug, Serialize)]
+pub struct CleanReport {
+    pub dry_run: bool,
+    pub broken: Vec<String>,
+    pub removed: Vec<String>,
+    pub failed: Vec<CleanFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CleanFailure {
+    pub domain: String,
+    pub error: Error,
+}
+
+impl CommandReport for CleanReport {}
+
+pub fn clean_broken_configs(dry_run: bool) -> Result<CleanReport> {
     log_step("🧹 Cleaning broken configurations...\n");
 
     let broken = detect_broken_configs()?;
+    let mut report = CleanReport {
+        dry_run,
+        broken: broken.clone(),
+        removed: vec![],
+        failed: vec![],
+    };
 
     if broken.is_empty() {
         log_success("✓ No broken configurations found");
-        return Ok(());
+        return Ok(report);
     }
 
     log_warning(&format!("Found {} broken configuration(s):", broken.len()));
@@ -65,32 +87,28 @@ pub fn clean_broken_configs(dry_run: bool) -> Result<(), String> {
 
     if dry_run {
         log_warning("\nDry run mode: no changes made");
-        return Ok(());
+        return Ok(report);
     }
 
     log_step("\n>  Removing broken configurations...");
-    for domain in &broken {
-        match remove_config_files(domain) {
-            Ok(_) => log_success(&format!("   ✓ Removed: {}", domain)),
-            Err(e) => log_warning(&format!("   ⚠️  Failed to remove {}: {}", domain, e)),
+    for domain in broken {
+        match remove_config_files(&domain) {
+            Ok(_) => {
+                log_success(&format!("   ✓ Removed: {}", domain));
+                report.removed.push(domain);
+            }
+            Err(e) => {
+                log_warning(&format!("   ⚠️  Failed to remove {}: {}", domain, e));
+                report.failed.push(CleanFailure { domain, error: e });
+            }
         }
     }
 
     log_success("\n✅ Cleanup complete!");
-    Ok(())
+    Ok(report)
 }
 
-pub fn remove_config_files(domain: &str) -> Result<(), String> {
-    let available_path = format!("{}/{}", NGINX_SITES_AVAILABLE, domain);
-    let enabled_path = format!("{}/{}", NGINX_SITES_ENABLED, domain);
-
-    if Path::new(&enabled_path).exists() {
-        fs::remove_file(&enabled_path).map_err(|e| format!("Failed to remove symlink: {}", e))?;
-    }
-
-    if Path::new(&available_path).exists() {
-        fs::remove_file(&available_path).map_err(|e| format!("Failed to remove config: {}", e))?;
-    }
-
-    Ok(())
+/// Delete every file of a site, whether it is enabled or not
+pub fn remove_config_files(domain: &str) -> Result<()> {
+    layout().remove_site(domain)
 }
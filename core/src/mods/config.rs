@@ -1,117 +1,223 @@
 use std::fs;
-use std::io::Write;
 use std::path::Path;
 
-use crate::mods::constants::{INDEX_HTML, NON_SSL_TEMPLATE, SSL_TEMPLATE};
+use serde::Serialize;
+
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::layout;
 use crate::mods::logger::{log_info, log_success};
-use crate::mods::models::DomainConfig;
-
-/// Load configuration template from embedded content
-pub fn load_template(template_path: &str) -> Result<String, String> {
-    match template_path {
-        "non_ssl_template.conf" => Ok(NON_SSL_TEMPLATE.to_string()),
-        "ssl_template.conf" => Ok(SSL_TEMPLATE.to_string()),
-        _ => Err(format!("Unknown template: {}", template_path)),
+use crate::mods::marker::managed_header;
+use crate::mods::models::{
+    BasicAuthConfig, CorsConfig, DomainConfig, LoadBalancing, RouteRewrite, UpstreamServer,
+};
+use crate::mods::paths::paths;
+use crate::mods::template::{load_template, render_template};
+
+/// Variables available to the virtual host templates
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct SiteContext<'a> {
+    domain_name: &'a str,
+    domain_hash: String,
+    max_body_size: &'a str,
+    ssl: bool,
+    upstream_name: String,
+    upstreams: Vec<UpstreamContext>,
+    routes: Vec<RouteContext<'a>>,
+    cors: Option<CorsContext<'a>>,
+    basic_auth: Option<&'a BasicAuthConfig>,
+    cache: Option<CacheContext<'a>>,
+    /// Directories of the files nginx serves, as nginx sees them (without --root)
+    web_root: &'static str,
+    acme_root: &'static str,
+    cert_dir: &'static str,
+    cache_dir: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamContext {
+    name: String,
+    method: Option<String>,
+    servers: Vec<UpstreamServer>,
+}
+
+#[derive(Debug, Serialize)]
+struct RouteContext<'a> {
+    path: &'a str,
+    regex: bool,
+    /// Route path without trailing slash, used by strip_prefix
+    prefix: &'a str,
+    upstream: String,
+    strip_prefix: bool,
+    rewrite: Option<&'a RouteRewrite>,
+    max_body_size: Option<&'a str>,
+    connect_timeout: Option<&'a str>,
+    send_timeout: Option<&'a str>,
+    read_timeout: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct CorsContext<'a> {
+    any_origin: bool,
+    origin_pattern: String,
+    allow_methods: &'a [String],
+    allow_headers: &'a [String],
+    allow_credentials: bool,
+    max_age: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct CacheContext<'a> {
+    zone: String,
+    valid: &'a str,
+    max_size: &'a str,
+    inactive: &'a str,
+}
+
+/// Name of the upstream pool generated for a domain (e.g. xynginc_api_example_com_d0c43d388506).
+/// The readable part maps several domains to the same name (a-b.com and a.b.com),
+/// so it ends with the start of the domain hash.
+pub fn get_upstream_name(domain: &str) -> String {
+    let sanitized: String = domain
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    format!("xynginc_{}_{}", sanitized, &crate::mods::utils::get_domain_hash(domain)[..12])
+}
+
+/// Name of the upstream pool generated for a route with its own backend
+pub fn get_route_upstream_name(domain: &str, index: usize) -> String {
+    format!("{}_r{}", get_upstream_name(domain), index)
+}
+
+/// Build the upstream pools of a domain and of its routes
+fn build_upstreams(config: &DomainConfig) -> Result<Vec<UpstreamContext>> {
+    let mut upstreams = vec![build_upstream(
+        get_upstream_name(&config.domain),
+        config.upstream_servers()?,
+        config.load_balancing,
+        config.hash_key.as_deref(),
+    )];
+
+    for (index, route) in config.routes.iter().enumerate() {
+        if let Some(servers) = route.upstream_servers(&config.host)? {
+            upstreams.push(build_upstream(
+                get_route_upstream_name(&config.domain, index),
+                servers,
+                route.load_balancing,
+                route.hash_key.as_deref(),
+            ));
+        }
     }
+
+    Ok(upstreams)
 }
 
-/// Replace template variables with actual values
-pub fn replace_template_variables(template: &str, variables: &[(&str, &str)]) -> String {
-    let mut result = template.to_string();
-    
-    for (key, value) in variables {
-        let placeholder = format!("{{{{{}}}}}", key);
-        result = result.replace(&placeholder, value);
+/// Resolve the balancing directive of an upstream pool
+fn build_upstream(
+    name: String,
+    servers: Vec<UpstreamServer>,
+    load_balancing: LoadBalancing,
+    hash_key: Option<&str>,
+) -> UpstreamContext {
+    let method = match load_balancing {
+        LoadBalancing::RoundRobin => None,
+        LoadBalancing::LeastConn => Some("least_conn".to_string()),
+        LoadBalancing::IpHash => Some("ip_hash".to_string()),
+        LoadBalancing::Hash => Some(format!("hash {} consistent", hash_key.unwrap_or_default())),
+    };
+
+    UpstreamContext { name, method, servers }
+}
+
+/// Build the path-based routes of a domain
+fn build_routes(config: &DomainConfig) -> Result<Vec<RouteContext<'_>>> {
+    let mut routes = vec![];
+
+    for (index, route) in config.routes.iter().enumerate() {
+        let upstream = if route.upstream_servers(&config.host)?.is_some() {
+            get_route_upstream_name(&config.domain, index)
+        } else {
+            get_upstream_name(&config.domain)
+        };
+
+        routes.push(RouteContext {
+            path: &route.path,
+            regex: route.regex,
+            prefix: route.path.trim_end_matches('/'),
+            upstream,
+            strip_prefix: route.strip_prefix,
+            rewrite: route.rewrite.as_ref(),
+            max_body_size: route.max_body_size.as_deref(),
+            connect_timeout: route.connect_timeout.as_deref(),
+            send_timeout: route.send_timeout.as_deref(),
+            read_timeout: route.read_timeout.as_deref(),
+        });
     }
-    
-    result
+
+    Ok(routes)
 }
 
-/// Replace HTML variables with actual values
-pub fn replace_html_variables(template: &str, variables: &[(&str, &str)]) -> String {
-    let mut result = template.to_string();
-    
-    for (key, value) in variables {
-        let placeholder = format!("{{{{{}}}}}", key);
-        result = result.replace(&placeholder, value);
+/// Build the CORS block of a domain
+fn build_cors(cors: &CorsConfig) -> CorsContext<'_> {
+    let patterns: Vec<String> = cors
+        .allow_origins
+        .iter()
+        .map(|origin| crate::mods::template::regex_escape(origin))
+        .collect();
+
+    CorsContext {
+        any_origin: cors.allow_origins.iter().any(|o| o == "*"),
+        origin_pattern: patterns.join("|"),
+        allow_methods: &cors.allow_methods,
+        allow_headers: &cors.allow_headers,
+        allow_credentials: cors.allow_credentials,
+        max_age: cors.max_age,
     }
-    
-    result
 }
 
-/// Generate nginx configuration using templates
-pub fn generate_nginx_config(config: &DomainConfig) -> Result<(), String> {
-    use crate::mods::constants::{NGINX_SITES_AVAILABLE};
-    
-    log_info(&format!("> Generating nginx configuration for {}", config.domain));
-    
+/// Render the nginx configuration of a domain without touching the filesystem.
+/// The config is expected to have been checked by `check_domain_config`,
+/// rendering doesn't validate the values it writes.
+pub fn render_nginx_config(config: &DomainConfig) -> Result<String> {
     // Load appropriate template based on SSL configuration
     let template_name = if config.ssl {
         "ssl_template.conf"
     } else {
         "non_ssl_template.conf"
     };
-    
-    let template = load_template(template_name)?;
-    
-    // Prepare template variables
-    let port_str = config.port.to_string();
-    let domain_hash = crate::mods::utils::get_domain_hash(&config.domain);
-    let variables: Vec<(&str, &str)> = vec![
-        ("DOMAIN_NAME", &config.domain),
-        ("BACKEND_HOST", &config.host),
-        ("BACKEND_PORT", &port_str),
-        ("MAX_BODY_SIZE", &config.max_body_size),
-        ("DOMAIN_HASH", &domain_hash),
-    ];
-    
-    // Replace variables in template
-    let nginx_config = replace_template_variables(&template, &variables);
-    
-    let config_path = format!("{}/{}", NGINX_SITES_AVAILABLE, config.domain);
-    let mut file = fs::File::create(&config_path)
-        .map_err(|e| format!("Failed to create config file: {}", e))?;
-
-    file.write_all(nginx_config.as_bytes())
-        .map_err(|e| format!("Failed to write config: {}", e))?;
-
-    log_success(&format!("✓ Config written to $/{domain}", domain = config.domain));
-
-    // Set up error pages, index page, and default config
-    log_info("   > Setting up web pages and default config...");
-    ensure_error_pages_exist(Some(&config.domain))
-        .map_err(|e| format!("Failed to set up error pages: {}", e))?;
-    ensure_index_page_exists()
-        .map_err(|e| format!("Failed to set up index page: {}", e))?;
-    ensure_default_config_exists()
-        .map_err(|e| format!("Failed to set up default config: {}", e))?;
 
-    Ok(())
-}
+    let context = SiteContext {
+        domain_name: &config.domain,
+        domain_hash: crate::mods::utils::get_domain_hash(&config.domain),
+        max_body_size: &config.max_body_size,
+        ssl: config.ssl,
+        upstream_name: get_upstream_name(&config.domain),
+        upstreams: build_upstreams(config)?,
+        routes: build_routes(config)?,
+        cors: config.cors.as_ref().map(build_cors),
+        basic_auth: config.basic_auth.as_ref(),
+        cache: config.cache.as_ref().map(|c| CacheContext {
+            zone: get_upstream_name(&config.domain),
+            valid: &c.valid,
+            max_size: &c.max_size,
+            inactive: &c.inactive,
+        }),
+        web_root: paths().unrooted(&paths().web_root),
+        acme_root: paths().unrooted(&paths().acme_webroot),
+        cert_dir: paths().unrooted(&paths().letsencrypt_live_dir),
+        cache_dir: paths().unrooted(&paths().cache_dir),
+    };
 
-/// Install or update the main nginx configuration
-/// This replaces /etc/nginx/nginx.conf with our optimized configuration
-pub fn ensure_nginx_main_config_exists() -> Result<(), String> {
-    use crate::mods::constants::NGINX_MAIN_CONFIG;
-    
-    let nginx_conf_path = "/etc/nginx/nginx.conf";
-    
-    log_info("> Installing main nginx configuration...");
-    
-    // Always overwrite the main config to ensure it's up to date
-    fs::write(nginx_conf_path, NGINX_MAIN_CONFIG)
-        .map_err(|e| format!("Failed to write main nginx config: {}", e))?;
-    
-    log_success(&format!("✓ Main nginx config installed at {}", nginx_conf_path));
-    
-    Ok(())
+    let rendered = render_template(template_name, &context)?;
+    Ok(format!("{}{}", managed_header(config)?, rendered))
 }
 
 /// Ensure the custom error page exists in the web directory
 /// Replace the default nginx welcome page with XyNginC index
-pub fn ensure_index_page_exists() -> Result<(), String> {
-    let index_page_path = "/var/www/html/index.html";
-    let default_nginx_index = "/var/www/html/index.nginx-debian.html";
+pub fn ensure_index_page_exists() -> Result<()> {
+    let index_page_path = &format!("{}/index.html", paths().web_root);
+    let default_nginx_index = &format!("{}/index.nginx-debian.html", paths().web_root);
 
     log_info("   > Setting up XyNginC index page");
 
@@ -119,63 +225,49 @@ pub fn ensure_index_page_exists() -> Result<(), String> {
     if Path::new(default_nginx_index).exists() {
         log_info("Removing default nginx welcome page");
         fs::remove_file(default_nginx_index)
-            .map_err(|e| format!("Failed to remove default nginx index: {}", e))?;
+            .map_err(Error::io("Failed to remove default nginx index"))?;
     }
 
     // Create XyNginC index page if it doesn't exist
     if !Path::new(index_page_path).exists() {
         log_info(" Creating XyNginC index page");
-        let index_html = generate_index_html();
+        let index_html = generate_index_html()?;
         fs::write(index_page_path, index_html)
-            .map_err(|e| format!("Failed to write index page: {}", e))?;
+            .map_err(Error::io("Failed to write index page"))?;
         
         log_success(&format!("   ✓ XyNginC index page created at {}", index_page_path));
     } else {
-        log_success(&format!("   ✓ XyNginC index page already exists."));
+        log_success("   ✓ XyNginC index page already exists.");
     }
 
     Ok(())
 }
 
-/// Generate XyNginC index HTML
-pub fn generate_index_html() -> String {
-    replace_html_variables(INDEX_HTML, &[
-        ("TITLE", "XyNginC"),
-        ("DESCRIPTION", "Nginx Controller for XyPriss Applications"),
-    ])
+/// Render the default site, serving the index and error pages from the web root
+pub fn render_default_config() -> Result<String> {
+    render_template("default.conf", minijinja::context! {
+        WEB_ROOT => paths().unrooted(&paths().web_root),
+    })
 }
 
-pub fn config_exists(domain: &str) -> bool {
-    use crate::mods::constants::NGINX_SITES_AVAILABLE;
-    let available_path = format!("{}/{}", NGINX_SITES_AVAILABLE, domain);
-    Path::new(&available_path).exists()
+/// Generate XyNginC index HTML
+pub fn generate_index_html() -> Result<String> {
+    render_template("index.html", minijinja::context! {
+        TITLE => "XyNginC",
+        DESCRIPTION => "Nginx Controller for XyPriss Applications",
+    })
 }
 
-/// Install or update the default nginx configuration
-/// This replaces /etc/nginx/sites-available/default with our professional default config
-pub fn ensure_default_config_exists() -> Result<(), String> {
-    use crate::mods::constants::{DEFAULT_CONFIG, NGINX_SITES_AVAILABLE};
-    
-    let default_config_path = format!("{}/default", NGINX_SITES_AVAILABLE);
-    
-    log_info("> Installing default nginx configuration...");
-    
-    // Always overwrite the default config to ensure it's up to date
-    fs::write(&default_config_path, DEFAULT_CONFIG)
-        .map_err(|e| format!("Failed to write default config: {}", e))?;
-    
-    log_success(&format!("   ✓ Default nginx config installed at {}", default_config_path));
-
-    Ok(())
+pub fn config_exists(domain: &str) -> bool {
+    layout().site_exists(domain)
 }
 
 /// Ensure error pages exist in the web directory
 /// If domain is provided, files are named using domain hash
-pub fn ensure_error_pages_exist(domain: Option<&str>) -> Result<(), String> {
-    use crate::mods::constants::{ERROR_301_HTML, ERROR_400_HTML, ERROR_401_HTML, ERROR_403_HTML, ERROR_404_HTML, ERROR_50X_HTML, ERROR_HTML};
+pub fn ensure_error_pages_exist(domain: Option<&str>) -> Result<()> {
     use crate::mods::utils::get_domain_hash;
     
-    let error_page_dir = "/var/www/html/errors";
+    let error_page_dir = &format!("{}/errors", paths().web_root);
     
     log_info("> Setting up pages...");
     
@@ -183,7 +275,7 @@ pub fn ensure_error_pages_exist(domain: Option<&str>) -> Result<(), String> {
     if !Path::new(error_page_dir).exists() {
         log_info("   Creating pages directory");
         fs::create_dir_all(error_page_dir)
-            .map_err(|e| format!("Failed to create error pages directory {}: {}", error_page_dir, e))?;
+            .map_err(Error::io(&format!("Failed to create error pages directory {}", error_page_dir)))?;
     }
     
     let suffix = if let Some(d) = domain {
@@ -194,25 +286,126 @@ pub fn ensure_error_pages_exist(domain: Option<&str>) -> Result<(), String> {
 
     // Write error pages
     let error_pages = vec![
-        (format!("{}301.html", suffix), ERROR_301_HTML),
-        (format!("{}400.html", suffix), ERROR_400_HTML),
-        (format!("{}401.html", suffix), ERROR_401_HTML),
-        (format!("{}403.html", suffix), ERROR_403_HTML),
-        (format!("{}404.html", suffix), ERROR_404_HTML),
-        (format!("{}50x.html", suffix), ERROR_50X_HTML),
-        (format!("{}error.html", suffix), ERROR_HTML),
+        (format!("{}301.html", suffix), "errors/301.html"),
+        (format!("{}400.html", suffix), "errors/400.html"),
+        (format!("{}401.html", suffix), "errors/401.html"),
+        (format!("{}403.html", suffix), "errors/403.html"),
+        (format!("{}404.html", suffix), "errors/404.html"),
+        (format!("{}50x.html", suffix), "errors/50x.html"),
+        (format!("{}error.html", suffix), "error.html"),
     ];
     
-    for (filename, content) in error_pages {
+    for (filename, template_name) in error_pages {
         let error_page_path = format!("{}/{}", error_page_dir, filename);
         
         // Only write if it doesn't exist to avoid unnecessary I/O
         if !Path::new(&error_page_path).exists() {
             log_info(&format!("   Writing error page: {}", filename));
-            fs::write(&error_page_path, content)
-                .map_err(|e| format!("Failed to write error page {}: {}", error_page_path, e))?;
+            fs::write(&error_page_path, load_template(template_name)?)
+                .map_err(Error::io(&format!("Failed to write error page {}", error_page_path)))?;
         }
     }
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+    use crate::mods::validate::check_domain_config;
+
+    /// Rendered config of a checked domain, `example.com` on port 3000 unless overridden
+    fn render(value: serde_json::Value) -> String {
+        let mut config = serde_json::json!({ "domain": "example.com", "port": 3000 });
+        config.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+        let config: DomainConfig = serde_json::from_value(config).unwrap();
+        render_nginx_config(&check_domain_config(&config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_host_and_port_as_a_one_member_pool() {
+        let _sandbox = sandbox();
+        let rendered = render(serde_json::json!({ "host": "127.0.0.1" }));
+        let name = get_upstream_name("example.com");
+
+        assert!(rendered.contains(&format!(
+            "upstream {} {{\n    server 127.0.0.1:3000 weight=1 max_fails=1 fail_timeout=10s;\n}}",
+            name
+        )));
+        assert!(rendered.contains(&format!("proxy_pass http://{};", name)));
+    }
+
+    #[test]
+    fn renders_upstream_pools_with_their_balancing_method() {
+        let _sandbox = sandbox();
+        let rendered = render(serde_json::json!({
+            "port": null,
+            "load_balancing": "least_conn",
+            "upstreams": [
+                { "host": "10.0.0.11", "port": 3000, "weight": 2 },
+                { "host": "10.0.0.12", "port": 3000, "max_fails": 3, "fail_timeout": "30s" },
+                { "host": "10.0.0.13", "port": 3000, "backup": true }
+            ]
+        }));
+
+        assert!(rendered.contains(&format!(
+            "upstream {} {{\n    least_conn;\n    \
+             server 10.0.0.11:3000 weight=2 max_fails=1 fail_timeout=10s;\n    \
+             server 10.0.0.12:3000 weight=1 max_fails=3 fail_timeout=30s;\n    \
+             server 10.0.0.13:3000 weight=1 max_fails=1 fail_timeout=10s backup;\n}}",
+            get_upstream_name("example.com")
+        )));
+
+        let rendered = render(serde_json::json!({ "load_balancing": "hash", "hash_key": "$request_uri" }));
+        assert!(rendered.contains("    hash $request_uri consistent;\n"));
+    }
+
+    #[test]
+    fn upstream_names_are_unique_per_domain() {
+        assert_ne!(get_upstream_name("a-b.com"), get_upstream_name("a.b.com"));
+        assert!(get_upstream_name("api.example.com").starts_with("xynginc_api_example_com_"));
+        assert_eq!(get_route_upstream_name("example.com", 2), format!("{}_r2", get_upstream_name("example.com")));
+    }
+
+    /// Block of the location opened by `header`, up to its closing brace
+    fn location<'a>(rendered: &'a str, header: &str) -> &'a str {
+        let start = rendered.find(header).unwrap_or_else(|| panic!("no {} in:\n{}", header, rendered));
+        let block = &rendered[start..];
+        &block[..block.find("\n    }\n").unwrap()]
+    }
+
+    #[test]
+    fn renders_routes_as_locations_with_their_own_backend() {
+        let _sandbox = sandbox();
+        let rendered = render(serde_json::json!({
+            "routes": [
+                { "path": "/api/", "port": 4000, "strip_prefix": true, "read_timeout": "5m" },
+                { "path": "/static", "max_body_size": "1M" },
+                { "path": "^/items/\\d+$", "regex": true, "upstreams": [{ "host": "10.0.0.2", "port": 4001 }] }
+            ]
+        }));
+
+        let api = location(&rendered, "    location ^~ /api/ {\n");
+        assert!(api.contains("        rewrite ^/api/?(.*)$ /$1 break;\n"));
+        assert!(api.contains(&format!("        proxy_pass http://{};\n", get_route_upstream_name("example.com", 0))));
+        assert!(api.contains("        proxy_read_timeout 5m;\n"));
+
+        // A route without backend keeps the domain pool
+        let static_files = location(&rendered, "    location ^~ /static {\n");
+        assert!(static_files.contains("        client_max_body_size 1M;\n"));
+        assert!(static_files.contains(&format!("        proxy_pass http://{};\n", get_upstream_name("example.com"))));
+        assert!(!static_files.contains("rewrite"));
+
+        let items = location(&rendered, "    location ~ \"^/items/\\d+$\" {\n");
+        assert!(items.contains(&format!("        proxy_pass http://{};\n", get_route_upstream_name("example.com", 2))));
+        assert!(rendered.contains(&format!(
+            "upstream {} {{\n    server 10.0.0.2:4001 ",
+            get_route_upstream_name("example.com", 2)
+        )));
+
+        // The other paths still go to the domain backend
+        let root = location(&rendered, "    location / {\n");
+        assert!(root.contains(&format!("proxy_pass http://{};", get_upstream_name("example.com"))));
+    }
+}
@@ -6,6 +6,15 @@ pub const INDEX_HTML: &str = include_str!("../configs/index.html");
 pub const DEFAULT_CONFIG: &str = include_str!("../configs/default.conf");
 pub const NGINX_MAIN_CONFIG: &str = include_str!("../configs/nginx_main.conf");
 
+// Template partials shared by the SSL and non-SSL templates
+pub const PARTIAL_UPSTREAMS: &str = include_str!("../configs/partials/upstreams.conf");
+pub const PARTIAL_ROUTES: &str = include_str!("../configs/partials/routes.conf");
+pub const PARTIAL_CORS: &str = include_str!("../configs/partials/cors.conf");
+pub const PARTIAL_BASIC_AUTH: &str = include_str!("../configs/partials/basic_auth.conf");
+pub const PARTIAL_CACHE: &str = include_str!("../configs/partials/cache.conf");
+pub const PARTIAL_DENY_SENSITIVE: &str = include_str!("../configs/partials/deny_sensitive.conf");
+pub const PARTIAL_SEO: &str = include_str!("../configs/partials/seo.conf");
+
 // Error pages
 pub const ERROR_301_HTML: &str = include_str!("../configs/errors/301.html");
 pub const ERROR_400_HTML: &str = include_str!("../configs/errors/400.html");
@@ -14,6 +23,28 @@ pub const ERROR_403_HTML: &str = include_str!("../configs/errors/403.html");
 pub const ERROR_404_HTML: &str = include_str!("../configs/errors/404.html");
 pub const ERROR_50X_HTML: &str = include_str!("../configs/errors/50x.html");
 
-pub const NGINX_SITES_AVAILABLE: &str = "/etc/nginx/sites-available";
-pub const NGINX_SITES_ENABLED: &str = "/etc/nginx/sites-enabled";
-pub const BACKUP_DIR: &str = "/var/backups/xynginc";
+// Filesystem locations are runtime settings, see paths.rs
+pub const SETTINGS_PATH: &str = "/etc/xynginc/xynginc.toml";
+
+// Embedded templates by lookup name, overridable from the template directory
+pub const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    ("ssl_template.conf", SSL_TEMPLATE),
+    ("non_ssl_template.conf", NON_SSL_TEMPLATE),
+    ("default.conf", DEFAULT_CONFIG),
+    ("nginx_main.conf", NGINX_MAIN_CONFIG),
+    ("index.html", INDEX_HTML),
+    ("error.html", ERROR_HTML),
+    ("errors/301.html", ERROR_301_HTML),
+    ("errors/400.html", ERROR_400_HTML),
+    ("errors/401.html", ERROR_401_HTML),
+    ("errors/403.html", ERROR_403_HTML),
+    ("errors/404.html", ERROR_404_HTML),
+    ("errors/50x.html", ERROR_50X_HTML),
+    ("partials/upstreams.conf", PARTIAL_UPSTREAMS),
+    ("partials/routes.conf", PARTIAL_ROUTES),
+    ("partials/cors.conf", PARTIAL_CORS),
+    ("partials/basic_auth.conf", PARTIAL_BASIC_AUTH),
+    ("partials/cache.conf", PARTIAL_CACHE),
+    ("partials/deny_sensitive.conf", PARTIAL_DENY_SENSITIVE),
+    ("partials/seo.conf", PARTIAL_SEO),
+];
@@ -1,86 +1,162 @@
 use colored::*;
+use serde::Serialize;
 use std::fs;
-use std::path::Path;
 
 use crate::mods::backup::create_backup;
 use crate::mods::cleanup::remove_config_files;
-use crate::mods::config::generate_nginx_config;
-use crate::mods::constants::{NGINX_SITES_AVAILABLE, NGINX_SITES_ENABLED};
+use crate::mods::config::{ensure_error_pages_exist, ensure_index_page_exists, render_default_config, render_nginx_config};
+use crate::mods::error::Result;
+use crate::mods::layout::layout;
 use crate::mods::logger::{log_info, log_step, log_success};
-use crate::mods::models::DomainConfig;
+use crate::mods::marker::is_managed;
+use crate::mods::models::{DomainConfig, LoadBalancing};
 use crate::mods::nginx::{reload_nginx, test_nginx};
-use crate::mods::ssl::setup_ssl;
+use crate::mods::output::CommandReport;
+use crate::mods::ssl::{certificate_exists, certificate_expiry, setup_ssl};
+use crate::mods::stage::Stage;
+use crate::mods::validate::{check_domain_config, normalize_domain};
+
+/// A site found in the site directory
+#[derive(Debug, Serialize)]
+pub struct DomainInfo {
+    pub domain: String,
+    pub enabled: bool,
+    /// Generated by xynginc (carries the ownership marker)
+    pub managed: bool,
+    pub ssl: bool,
+    pub certificate_expiry: Option<String>,
+}
 
-pub fn list_domains() -> Result<(), String> {
-    log_step("Configured domains:\n");
+#[derive(Debug, Serialize)]
+pub struct DomainList {
+    pub domains: Vec<DomainInfo>,
+}
+
+impl CommandReport for DomainList {}
+
+/// Read the configured sites, the default site excluded
+pub fn collect_domains() -> Result<Vec<DomainInfo>> {
+    let layout = layout();
 
-    let sites = fs::read_dir(NGINX_SITES_AVAILABLE)
-        .map_err(|e| format!("Failed to read sites-available: {}", e))?;
-
-    let mut count = 0;
-    for entry in sites {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let name = entry.file_name();
-        let name_str = name.to_string_lossy();
-
-        if name_str != "default" {
-            let enabled = Path::new(&format!("{}/{}", NGINX_SITES_ENABLED, name_str)).exists();
-            let status = if enabled {
-                "✓ enabled".green()
-            } else {
-                "◯ disabled".normal()
-            };
-            println!("   {} - {}", name_str, status);
-            count += 1;
+    let mut domains = vec![];
+    for (name, path) in layout.list_sites()? {
+        if name == "default" {
+            continue;
         }
-    }
 
-    if count == 0 {
-        log_info("   (no domains configured)");
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let ssl = content.contains("ssl_certificate ");
+        domains.push(DomainInfo {
+            enabled: layout.is_enabled(&name),
+            managed: is_managed(&content),
+            ssl,
+            certificate_expiry: if ssl { certificate_expiry(&name) } else { None },
+            domain: name,
+        });
     }
 
-    Ok(())
+    domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+    Ok(domains)
 }
 
-pub fn add_domain(domain: &str, port: u16, ssl: bool, email: Option<&str>, host: Option<&str>, max_body_size: Option<&str>) -> Result<(), String> {
-    if ssl && email.is_none() {
-        return Err("Email is required when SSL is enabled".to_string());
+pub fn list_domains() -> Result<DomainList> {
+    log_step("Configured domains:\n");
+
+    let domains = collect_domains()?;
+    for info in &domains {
+        let status = if info.enabled {
+            "✓ enabled".green()
+        } else {
+            "◯ disabled".normal()
+        };
+        log_info(&format!("   {} - {}", info.domain, status));
     }
 
+    if domains.is_empty() {
+        log_info("   (no domains configured)");
+    }
+
+    Ok(DomainList { domains })
+}
+
+/// Add a single proxied domain, see `add_domain_config` for the other options
+pub fn add_domain(domain: &str, port: u16, ssl: bool, email: Option<&str>, host: Option<&str>, max_body_size: Option<&str>) -> Result<()> {
     let config = DomainConfig {
         domain: domain.to_string(),
-        port,
+        port: Some(port),
         ssl,
         email: email.map(|s| s.to_string()),
         host: host.unwrap_or("localhost").to_string(),
         max_body_size: max_body_size.unwrap_or("20M").to_string(),
+        upstreams: vec![],
+        load_balancing: LoadBalancing::default(),
+        hash_key: None,
+        routes: vec![],
+        cors: None,
+        basic_auth: None,
+        cache: None,
     };
 
-    log_step(&format!("Adding domain: {}", domain));
-    
-    // Backup avant modification
-    create_backup()?;
-    
-    generate_nginx_config(&config)?;
-    enable_site(domain)?;
+    add_domain_config(&config)
+}
+
+/// Generate, enable and (with SSL) certify a domain, then reload nginx.
+/// The site is staged and tested before going live: a rejected site leaves
+/// the live configuration untouched.
+pub fn add_domain_config(config: &DomainConfig) -> Result<()> {
+    let config = &check_domain_config(config)?;
+
+    log_step(&format!("Adding domain: {}", config.domain));
 
-    if ssl {
-        setup_ssl(&config)?;
+    // Backup avant modification
+    create_backup("add")?;
+
+    // Sans certificat, le site démarre en HTTP : certbot en a besoin
+    let pending_ssl = config.ssl && !certificate_exists(&config.domain);
+    install_site(&DomainConfig {
+        ssl: config.ssl && !pending_ssl,
+        ..config.clone()
+    })?;
+
+    log_info("   > Setting up web pages...");
+    ensure_error_pages_exist(Some(&config.domain)).map_err(|e| e.context("Failed to set up error pages"))?;
+    ensure_index_page_exists().map_err(|e| e.context("Failed to set up index page"))?;
+
+    if pending_ssl {
+        reload_nginx()?;
+        setup_ssl(config)?;
+        install_site(config)?;
     }
 
-    // Test avant reload
-    test_nginx()?;
     reload_nginx()?;
 
-    log_success(&format!("✅ Domain {} added successfully!", domain));
+    log_success(&format!("✅ Domain {} added successfully!", config.domain));
     Ok(())
 }
 
-pub fn remove_domain(domain: &str) -> Result<(), String> {
+/// Stage the site of a domain with the default site, test them and swap them in
+fn install_site(config: &DomainConfig) -> Result<()> {
+    log_info(&format!("> Generating nginx configuration for {}", config.domain));
+
+    let mut stage = Stage::new()?;
+    stage.write_site("default", &render_default_config()?)?;
+    stage.write_site(&config.domain, &render_nginx_config(config)?)?;
+    stage.enable_site(&config.domain)?;
+    stage.validate()?;
+    stage.commit()?;
+
+    log_success(&format!("   ✓ Config written to {}", layout().site_path(&config.domain)));
+    Ok(())
+}
+
+/// Remove the site files of a domain and reload nginx
+pub fn remove_domain(domain: &str) -> Result<()> {
+    // The name becomes a file path: refuse anything but a valid domain
+    let domain = &normalize_domain(domain)?;
     log_step(&format!("Removing domain: {}", domain));
 
     // Backup avant suppression
-    create_backup()?;
+    create_backup("remove")?;
 
     remove_config_files(domain)?;
     
@@ -91,19 +167,18 @@ pub fn remove_domain(domain: &str) -> Result<(), String> {
     Ok(())
 }
 
-pub fn enable_site(domain: &str) -> Result<(), String> {
-    let available_path = format!("{}/{}", NGINX_SITES_AVAILABLE, domain);
-    let enabled_path = format!("{}/{}", NGINX_SITES_ENABLED, domain);
-
-    // Supprimer le symlink existant s'il existe
-    if Path::new(&enabled_path).exists() {
-        fs::remove_file(&enabled_path)
-            .map_err(|e| format!("Failed to remove existing symlink: {}", e))?;
-    }
-
-    std::os::unix::fs::symlink(&available_path, &enabled_path)
-        .map_err(|e| format!("Failed to create symlink: {}", e))?;
+/// Make nginx load the site of a domain (symlink in sites-enabled, or `<domain>.conf` in conf.d)
+pub fn enable_site(domain: &str) -> Result<()> {
+    layout().enable_site(domain)?;
     
     log_success("   ✓ Site enabled");
     Ok(())
 }
+
+/// Stop nginx from loading the site of a domain, keeping its configuration
+pub fn disable_site(domain: &str) -> Result<()> {
+    layout().disable_site(domain)?;
+
+    log_success("   ✓ Site disabled");
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io;
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+/// Errors returned by xynginc commands.
+///
+/// Every variant has a stable identifier (shown in JSON output) and its own
+/// process exit code, so scripts can tell failures apart without parsing messages.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration could not be parsed
+    ConfigParse(String),
+    /// The configuration parsed but describes something that can't be generated
+    InvalidConfig(String),
+    /// `nginx -t` rejected the configuration
+    NginxTest(String),
+    /// nginx could not be reloaded or queried
+    NginxService(String),
+    /// certbot could not obtain a certificate
+    Certbot(String),
+    /// Not running as root, or a file could not be accessed
+    PermissionDenied(String),
+    /// The requested backup does not exist
+    BackupMissing(String),
+    /// The backup was taken with another site layout than the live one
+    BackupIncompatible(String),
+    /// A template could not be loaded or rendered
+    Template(String),
+    /// Reading or writing a file failed
+    Io(String),
+    /// An external program could not be run or failed
+    Command(String),
+    /// `check` found missing requirements
+    MissingRequirements(String),
+    /// `apply` completed but some domains could not be applied
+    PartialApply(String),
+    Other(String),
+}
+
+impl Error {
+    /// Stable identifier of the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ConfigParse(_) => "config_parse_error",
+            Error::InvalidConfig(_) => "invalid_config",
+            Error::NginxTest(_) => "nginx_test_failed",
+            Error::NginxService(_) => "nginx_service_failed",
+            Error::Certbot(_) => "certbot_failed",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::BackupMissing(_) => "backup_missing",
+            Error::BackupIncompatible(_) => "backup_incompatible",
+            Error::Template(_) => "template_error",
+            Error::Io(_) => "io_error",
+            Error::Command(_) => "command_failed",
+            Error::MissingRequirements(_) => "missing_requirements",
+            Error::PartialApply(_) => "partial_apply",
+            Error::Other(_) => "error",
+        }
+    }
+
+    /// Process exit code; 2 is reserved for `apply --plan` with pending changes
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::ConfigParse(_) => 3,
+            Error::InvalidConfig(_) => 4,
+            Error::NginxTest(_) => 5,
+            Error::NginxService(_) => 6,
+            Error::Certbot(_) => 7,
+            Error::PermissionDenied(_) => 8,
+            Error::BackupMissing(_) => 9,
+            Error::Template(_) => 10,
+            Error::Io(_) => 11,
+            Error::Command(_) => 12,
+            Error::MissingRequirements(_) => 13,
+            Error::PartialApply(_) => 14,
+            Error::BackupIncompatible(_) => 15,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::ConfigParse(m)
+            | Error::InvalidConfig(m)
+            | Error::NginxTest(m)
+            | Error::NginxService(m)
+            | Error::Certbot(m)
+            | Error::PermissionDenied(m)
+            | Error::BackupMissing(m)
+            | Error::BackupIncompatible(m)
+            | Error::Template(m)
+            | Error::Io(m)
+            | Error::Command(m)
+            | Error::MissingRequirements(m)
+            | Error::PartialApply(m)
+            | Error::Other(m) => m,
+        }
+    }
+
+    /// Prefix the message (e.g. with the domain at fault), keeping the error kind
+    pub fn context(self, context: &str) -> Error {
+        self.map_message(|m| format!("{}: {}", context, m))
+    }
+
+    /// Rewrite the message, keeping the error kind
+    pub fn map_message(self, f: impl FnOnce(&str) -> String) -> Error {
+        let message = f(self.message());
+        match self {
+            Error::ConfigParse(_) => Error::ConfigParse(message),
+            Error::InvalidConfig(_) => Error::InvalidConfig(message),
+            Error::NginxTest(_) => Error::NginxTest(message),
+            Error::NginxService(_) => Error::NginxService(message),
+            Error::Certbot(_) => Error::Certbot(message),
+            Error::PermissionDenied(_) => Error::PermissionDenied(message),
+            Error::BackupMissing(_) => Error::BackupMissing(message),
+            Error::BackupIncompatible(_) => Error::BackupIncompatible(message),
+            Error::Template(_) => Error::Template(message),
+            Error::Io(_) => Error::Io(message),
+            Error::Command(_) => Error::Command(message),
+            Error::MissingRequirements(_) => Error::MissingRequirements(message),
+            Error::PartialApply(_) => Error::PartialApply(message),
+            Error::Other(_) => Error::Other(message),
+        }
+    }
+
+    /// Map an I/O error with some context, keeping permission problems apart.
+    /// Meant for `map_err`: `fs::write(..).map_err(Error::io("Failed to write config"))`
+    pub fn io(context: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |e| {
+            let message = format!("{}: {}", context, e);
+            if e.kind() == io::ErrorKind::PermissionDenied {
+                Error::PermissionDenied(message)
+            } else {
+                Error::Io(message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_string())
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("exit_code", &self.exit_code())?;
+        state.serialize_field("message", self.message())?;
+        state.end()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,299 @@
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::mods::error::{Error, Result};
+use crate::mods::validate::config_schema;
+
+/// Prefix of a reference replaced by the content of a file
+const FILE_PREFIX: &str = "file:";
+
+/// Replace the references found in the strings of a parsed config, before it
+/// is deserialized:
+///
+/// - `${VAR}`: value of the environment variable, which must be set
+/// - `${VAR:-default}`: value of the variable, `default` when it is unset or empty
+/// - `${file:path}`: content of the file without its trailing newline, a relative
+///   path being resolved from `base_dir` (the directory of the config file).
+///   Fields holding a path that nginx opens (`basic_auth.user_file`) take the path itself.
+/// - `$${`: a literal `${`
+///
+/// Only string values are interpolated, so a value can't change the structure
+/// of the file whatever it contains. A string made of a single reference takes
+/// the type of its field in the config schema: `port: "${PORT}"` becomes a
+/// number, `max_body_size: "${SIZE}"` stays a string whatever its value.
+/// Every undefined variable is reported at once.
+pub fn interpolate(value: &mut Value, base_dir: Option<&Path>) -> Result<()> {
+    let schema = config_schema().0;
+    let root = schema.as_value();
+    let mut undefined: Vec<String> = vec![];
+    interpolate_value(value, "", Some(root), root, base_dir, &mut undefined)?;
+
+    if !undefined.is_empty() {
+        return Err(Error::ConfigParse(format!(
+            "Undefined variable(s) in config: {} (set them or give a default with ${{VAR:-default}})",
+            undefined.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Interpolate the strings of a value, `field` being its path (e.g. `domains[0].port`)
+/// and `schema` its schema, `None` for a field the config does not know
+fn interpolate_value(
+    value: &mut Value,
+    field: &str,
+    schema: Option<&Value>,
+    root: &Value,
+    base_dir: Option<&Path>,
+    undefined: &mut Vec<String>,
+) -> Result<()> {
+    match value {
+        Value::String(text) => {
+            let interpolated = interpolate_string(text, base_dir, undefined)
+                .map_err(|e| e.map_message(|m| format!("{} in {}", m, field)))?;
+            match interpolated {
+                Some((text, true)) => *value = typed_value(text, schema, root),
+                Some((text, false)) => *value = Value::String(text),
+                None => {}
+            }
+        }
+        Value::Array(items) => {
+            let item_schema = schema.and_then(|schema| find(schema, root, |s| s.get("items")));
+            for (index, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", field, index), item_schema, root, base_dir, undefined)?;
+            }
+        }
+        Value::Object(fields) => {
+            for (name, item) in fields.iter_mut() {
+                let field = if field.is_empty() { name.clone() } else { format!("{}.{}", field, name) };
+                let field_schema =
+                    schema.and_then(|schema| find(schema, root, |s| s.get("properties")?.get(name.as_str())));
+                interpolate_value(item, &field, field_schema, root, base_dir, undefined)?;
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// New value of a string holding references, `None` when it has none. The
+/// flag tells whether the string was a single reference and nothing else.
+fn interpolate_string(
+    text: &str,
+    base_dir: Option<&Path>,
+    undefined: &mut Vec<String>,
+) -> Result<Option<(String, bool)>> {
+    if !text.contains('$') {
+        return Ok(None);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut references = 0;
+    let mut literal = false;
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        literal |= start > 0;
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            literal = true;
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix("${") else {
+            output.push('$');
+            literal = true;
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(end) = after.find('}') else {
+            return Err(Error::ConfigParse(format!("Unterminated reference '{}'", rest)));
+        };
+
+        let reference = &after[..end];
+        rest = &after[end + 1..];
+        references += 1;
+        match resolve(reference, base_dir)? {
+            Some(value) => output.push_str(&value),
+            None => {
+                if !undefined.iter().any(|name| name == reference) {
+                    undefined.push(reference.to_string());
+                }
+            }
+        }
+    }
+    output.push_str(rest);
+    literal |= !rest.is_empty();
+
+    Ok(Some((output, references == 1 && !literal)))
+}
+
+/// Value of a field made of a single reference: "${PORT}" stands for the
+/// number itself when the field can't be a string, "port ${PORT}" stays a string
+fn typed_value(text: String, schema: Option<&Value>, root: &Value) -> Value {
+    if let Some(schema) = schema
+        && !accepts(schema, root, "string")
+    {
+        if (accepts(schema, root, "integer") || accepts(schema, root, "number"))
+            && let Ok(number) = text.parse::<serde_json::Number>()
+        {
+            return Value::Number(number);
+        }
+        if accepts(schema, root, "boolean")
+            && let Ok(boolean) = text.parse::<bool>()
+        {
+            return Value::Bool(boolean);
+        }
+    }
+    Value::String(text)
+}
+
+/// Whether a schema, or one of its alternatives, allows values of a JSON type
+fn accepts(schema: &Value, root: &Value, kind: &str) -> bool {
+    alternatives(schema, root).into_iter().any(|schema| match schema.get("type") {
+        Some(Value::String(name)) => name == kind,
+        Some(Value::Array(names)) => names.iter().any(|name| name == kind),
+        _ => false,
+    })
+}
+
+/// First match of `get` in a schema or its alternatives
+fn find<'a>(schema: &'a Value, root: &'a Value, get: impl Fn(&'a Value) -> Option<&'a Value>) -> Option<&'a Value> {
+    alternatives(schema, root).into_iter().find_map(get)
+}
+
+/// A schema, with its `$ref` followed, and the schemas of its `anyOf`,
+/// `oneOf` and `allOf` branches (e.g. `Option<CorsConfig>`)
+fn alternatives<'a>(schema: &'a Value, root: &'a Value) -> Vec<&'a Value> {
+    let schema = match schema.get("$ref").and_then(Value::as_str).and_then(|r| r.strip_prefix('#')) {
+        Some(pointer) => root.pointer(pointer).unwrap_or(schema),
+        None => schema,
+    };
+    let mut schemas = vec![schema];
+    for key in ["anyOf", "oneOf", "allOf"] {
+        if let Some(Value::Array(branches)) = schema.get(key) {
+            for branch in branches {
+                schemas.extend(alternatives(branch, root));
+            }
+        }
+    }
+    schemas
+}
+
+/// Value of a reference, `None` for an unset variable without default
+fn resolve(reference: &str, base_dir: Option<&Path>) -> Result<Option<String>> {
+    if let Some(path) = reference.strip_prefix(FILE_PREFIX) {
+        let path = Path::new(path.trim());
+        let path = match base_dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        };
+        let content = fs::read_to_string(&path).map_err(|e| {
+            Error::ConfigParse(format!("Failed to read '{}' referenced by the config: {}", path.display(), e))
+        })?;
+        return Ok(Some(content.trim_end_matches(['\n', '\r']).to_string()));
+    }
+
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+
+    let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_name {
+        return Err(Error::ConfigParse(format!(
+            "Invalid reference '${{{}}}', expected ${{VAR}}, ${{VAR:-default}} or ${{file:path}}",
+            reference
+        )));
+    }
+
+    let value = env::var(name).ok();
+    Ok(match (value, default) {
+        (Some(value), Some(default)) if value.is_empty() => Some(default.to_string()),
+        (Some(value), _) => Some(value),
+        (None, default) => default.map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::models::Config;
+
+    #[test]
+    fn single_references_take_the_type_of_their_field() {
+        let mut value = serde_json::json!({
+            "defaults": { "ssl": "${XYNGINC_TEST_UNSET_SSL:-false}" },
+            "domains": [{
+                "domain": "example.com",
+                "maxBodySize": "${XYNGINC_TEST_UNSET_SIZE:-512}",
+                "upstreams": [{
+                    "host": "127.0.0.1",
+                    "port": "${XYNGINC_TEST_UNSET_PORT:-3000}",
+                    "fail_timeout": "${XYNGINC_TEST_UNSET_TIMEOUT:-30}"
+                }],
+                "cors": { "max_age": "${XYNGINC_TEST_UNSET_AGE:-600}", "allow_origins": ["${XYNGINC_TEST_UNSET_ORIGIN:-1}"] }
+            }]
+        });
+        interpolate(&mut value, None).unwrap();
+
+        let domain = &value["domains"][0];
+        assert_eq!(value["defaults"]["ssl"], serde_json::json!(false));
+        assert_eq!(domain["maxBodySize"], serde_json::json!("512"));
+        assert_eq!(domain["upstreams"][0]["port"], serde_json::json!(3000));
+        assert_eq!(domain["upstreams"][0]["fail_timeout"], serde_json::json!("30"));
+        assert_eq!(domain["cors"]["max_age"], serde_json::json!(600));
+        assert_eq!(domain["cors"]["allow_origins"][0], serde_json::json!("1"));
+        assert!(serde_json::from_value::<Config>(value).is_ok());
+    }
+
+    #[test]
+    fn inlines_files_relative_to_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("email"), "admin@example.com\n").unwrap();
+        let mut value = serde_json::json!({ "domains": [{ "domain": "example.com", "email": "${file:email}" }] });
+        interpolate(&mut value, Some(dir.path())).unwrap();
+        assert_eq!(value["domains"][0]["email"], serde_json::json!("admin@example.com"));
+
+        let mut value = serde_json::json!({ "domains": [{ "email": "${file:missing}" }] });
+        let error = interpolate(&mut value, Some(dir.path())).unwrap_err();
+        assert!(error.to_string().contains("domains[0].email"), "{}", error);
+    }
+
+    #[test]
+    fn replaces_variables_defaults_and_escapes() {
+        let mut value = serde_json::json!({
+            "set": "${PATH}",
+            "default": "api.${XYNGINC_TEST_UNSET_ZONE:-staging}.example.com",
+            "empty_default": "${XYNGINC_TEST_UNSET_EMPTY:-}",
+            "escaped": "$${PATH} costs $5"
+        });
+        interpolate(&mut value, None).unwrap();
+        assert_eq!(value["set"], serde_json::json!(env::var("PATH").unwrap()));
+        assert_eq!(value["default"], serde_json::json!("api.staging.example.com"));
+        assert_eq!(value["empty_default"], serde_json::json!(""));
+        assert_eq!(value["escaped"], serde_json::json!("${PATH} costs $5"));
+    }
+
+    #[test]
+    fn reports_every_undefined_variable_at_once() {
+        let mut value = serde_json::json!({
+            "domains": [
+                { "domain": "${XYNGINC_TEST_UNSET_A}", "port": "${XYNGINC_TEST_UNSET_B}" },
+                { "domain": "${XYNGINC_TEST_UNSET_A}.example.com" }
+            ]
+        });
+        let error = interpolate(&mut value, None).unwrap_err();
+        assert!(matches!(error, Error::ConfigParse(_)));
+        assert!(
+            error.to_string().contains("Undefined variable(s) in config: XYNGINC_TEST_UNSET_A, XYNGINC_TEST_UNSET_B ("),
+            "{}",
+            error
+        );
+    }
+}
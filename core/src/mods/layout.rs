@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::mods::error::{Error, Result};
+use crate::mods::paths::{paths, Paths};
+
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+/// Suffix given to a site of the conf.d layout to disable it
+const DISABLED_SUFFIX: &str = ".disabled";
+
+/// Distributions whose nginx package only reads conf.d
+const CONF_D_DISTROS: &[&str] = &["rhel", "centos", "fedora", "rocky", "almalinux", "amzn", "ol", "alpine"];
+
+/// How nginx finds the site configurations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layout {
+    /// Debian/Ubuntu: files in sites-available, enabled by a symlink in sites-enabled
+    #[serde(rename = "sites-enabled", alias = "sites_enabled")]
+    SitesEnabled,
+    /// RHEL/Fedora/Alpine: `<site>.conf` in conf.d, disabled by renaming it to `<site>.conf.disabled`
+    #[serde(rename = "conf.d", alias = "conf_d")]
+    ConfD,
+}
+
+/// Directory holding site files
+pub struct SiteDir {
+    /// Name of the directory inside a backup
+    pub name: &'static str,
+    pub path: String,
+    /// Whether a restore replaces the whole directory instead of overwriting files
+    pub replace_on_restore: bool,
+}
+
+impl Layout {
+    /// Parse a layout name as written in the settings or the environment
+    pub fn parse(value: &str) -> Option<Layout> {
+        match value {
+            "sites-enabled" | "sites_enabled" | "debian" => Some(Layout::SitesEnabled),
+            "conf.d" | "conf_d" | "rhel" => Some(Layout::ConfD),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::SitesEnabled => "sites-enabled",
+            Layout::ConfD => "conf.d",
+        }
+    }
+
+    /// Guess the layout from the include lines of nginx.conf, then from the distribution
+    pub fn detect() -> Layout {
+        Layout::detect_in(paths())
+    }
+
+    fn detect_in(paths: &Paths) -> Layout {
+        if let Ok(content) = fs::read_to_string(&paths.nginx_conf) {
+            let includes: Vec<&str> = content
+                .lines()
+                .map(str::trim)
+                .filter_map(|line| line.strip_prefix("include "))
+                .collect();
+            let includes_dir = |dir: &str| {
+                let dir = format!("{}/", paths.unrooted(dir));
+                includes.iter().any(|include| include.starts_with(&dir))
+            };
+
+            if includes_dir(&paths.sites_enabled) && Path::new(&paths.sites_enabled).is_dir() {
+                return Layout::SitesEnabled;
+            }
+            if includes_dir(&paths.conf_d) {
+                return Layout::ConfD;
+            }
+        }
+
+        let os_release = fs::read_to_string(format!("{}/etc/os-release", paths.root)).unwrap_or_default();
+        let is_conf_d_distro = os_release
+            .lines()
+            .filter_map(|line| line.strip_prefix("ID=").or_else(|| line.strip_prefix("ID_LIKE=")))
+            .flat_map(|ids| ids.trim_matches('"').split_whitespace())
+            .any(|id| CONF_D_DISTROS.contains(&id));
+
+        if is_conf_d_distro && !Path::new(&paths.sites_enabled).is_dir() {
+            Layout::ConfD
+        } else {
+            Layout::SitesEnabled
+        }
+    }
+
+    /// Directories holding the site files, in backup order
+    pub fn site_dirs(&self) -> Vec<SiteDir> {
+        let paths = paths();
+        match self {
+            Layout::SitesEnabled => vec![
+                SiteDir {
+                    name: "sites-available",
+                    path: paths.sites_available.clone(),
+                    replace_on_restore: false,
+                },
+                SiteDir {
+                    name: "sites-enabled",
+                    path: paths.sites_enabled.clone(),
+                    replace_on_restore: true,
+                },
+            ],
+            Layout::ConfD => vec![SiteDir {
+                name: "conf.d",
+                path: paths.conf_d.clone(),
+                replace_on_restore: true,
+            }],
+        }
+    }
+
+    /// File holding the configuration of a site
+    pub fn site_path(&self, name: &str) -> String {
+        match self {
+            Layout::SitesEnabled => format!("{}/{}", paths().sites_available, name),
+            Layout::ConfD => format!("{}/{}.conf", paths().conf_d, name),
+        }
+    }
+
+    /// Entry that makes the site active: the sites-enabled symlink, or the
+    /// `.conf.disabled` file of conf.d that must be absent
+    pub fn switch_path(&self, name: &str) -> String {
+        match self {
+            Layout::SitesEnabled => format!("{}/{}", paths().sites_enabled, name),
+            Layout::ConfD => format!("{}{}", self.site_path(name), DISABLED_SUFFIX),
+        }
+    }
+
+    /// Every path a site may own, the switch entry first
+    pub fn site_files(&self, name: &str) -> Vec<String> {
+        vec![self.switch_path(name), self.site_path(name)]
+    }
+
+    /// Same entries as `site_files`, as (site directory name, file name), which
+    /// is how they are stored in a backup
+    pub fn site_entries(&self, name: &str) -> Vec<(&'static str, String)> {
+        match self {
+            Layout::SitesEnabled => vec![("sites-enabled", name.to_string()), ("sites-available", name.to_string())],
+            Layout::ConfD => vec![
+                ("conf.d", format!("{}.conf{}", name, DISABLED_SUFFIX)),
+                ("conf.d", format!("{}.conf", name)),
+            ],
+        }
+    }
+
+    /// Whether nginx loads the site
+    pub fn is_enabled(&self, name: &str) -> bool {
+        match self {
+            Layout::SitesEnabled => Path::new(&self.switch_path(name)).exists(),
+            Layout::ConfD => Path::new(&self.site_path(name)).exists(),
+        }
+    }
+
+    /// Whether a configuration exists for the site, enabled or not
+    pub fn site_exists(&self, name: &str) -> bool {
+        self.site_files(name).iter().any(|path| Path::new(path).exists())
+    }
+
+    /// Sites with their configuration file, sorted by name
+    pub fn list_sites(&self) -> Result<Vec<(String, String)>> {
+        let dir = match self {
+            Layout::SitesEnabled => &paths().sites_available,
+            Layout::ConfD => &paths().conf_d,
+        };
+        let entries = fs::read_dir(dir).map_err(Error::io(&format!("Failed to read {}", dir)))?;
+
+        let mut sites = vec![];
+        for entry in entries {
+            let entry = entry.map_err(Error::io("Failed to read entry"))?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let name = match self {
+                Layout::SitesEnabled => Some(file_name.as_str()),
+                Layout::ConfD => file_name
+                    .strip_suffix(DISABLED_SUFFIX)
+                    .unwrap_or(&file_name)
+                    .strip_suffix(".conf"),
+            };
+            let Some(name) = name else { continue };
+
+            let path = entry.path().to_string_lossy().to_string();
+            match sites.iter_mut().find(|(site, _)| site == name) {
+                // With both files present, the enabled one is what nginx reads
+                Some(site) if !file_name.ends_with(DISABLED_SUFFIX) => site.1 = path,
+                Some(_) => {}
+                None => sites.push((name.to_string(), path)),
+            }
+        }
+
+        sites.sort();
+        Ok(sites)
+    }
+
+    /// Make nginx load a site
+    pub fn enable_site(&self, name: &str) -> Result<()> {
+        let site = self.site_path(name);
+        let switch = self.switch_path(name);
+        match self {
+            Layout::SitesEnabled => {
+                remove_file(&switch)?;
+                symlink(&site, &switch).map_err(Error::io("Failed to create symlink"))?;
+            }
+            Layout::ConfD => {
+                if Path::new(&site).exists() {
+                    remove_file(&switch)?;
+                } else if Path::new(&switch).exists() {
+                    fs::rename(&switch, &site).map_err(Error::io(&format!("Failed to enable {}", name)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the configuration of a site but stop nginx from loading it
+    pub fn disable_site(&self, name: &str) -> Result<()> {
+        let switch = self.switch_path(name);
+        match self {
+            Layout::SitesEnabled => remove_file(&switch),
+            Layout::ConfD => {
+                let site = self.site_path(name);
+                if Path::new(&site).exists() {
+                    fs::rename(&site, &switch).map_err(Error::io(&format!("Failed to disable {}", name)))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Delete every file of a site
+    pub fn remove_site(&self, name: &str) -> Result<()> {
+        for path in self.site_files(name) {
+            remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Site at fault in a line of `nginx -t` output, e.g.
+    /// "... in /etc/nginx/sites-enabled/example.com:12"
+    pub fn site_in_error(&self, line: &str) -> Option<String> {
+        let paths = paths();
+        let dir = match self {
+            Layout::SitesEnabled => &paths.sites_enabled,
+            Layout::ConfD => &paths.conf_d,
+        };
+        // nginx reports the paths it read, i.e. without the root prefix
+        let prefix = format!("in {}/", paths.unrooted(dir));
+
+        let start = line.find(&prefix)? + prefix.len();
+        let file = &line[start..start + line[start..].find(':')?];
+        match self {
+            Layout::SitesEnabled => Some(file.to_string()),
+            Layout::ConfD => file.strip_suffix(".conf").map(str::to_string),
+        }
+    }
+}
+
+/// Layout of the process: the one set in the settings, otherwise the detected one
+pub fn layout() -> Layout {
+    *LAYOUT.get_or_init(|| paths().layout.unwrap_or_else(Layout::detect))
+}
+
+fn remove_file(path: &str) -> Result<()> {
+    if Path::new(path).symlink_metadata().is_ok() {
+        fs::remove_file(path).map_err(Error::io(&format!("Failed to remove {}", path)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    /// Paths of a new root holding the given files
+    fn root_with(files: &[(&str, &str)], dirs: &[&str]) -> (tempfile::TempDir, Paths) {
+        let root = tempfile::tempdir().unwrap();
+        for dir in dirs {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for (path, content) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let paths = Paths::load(Some(root.path().to_str().unwrap())).unwrap();
+        (root, paths)
+    }
+
+    #[test]
+    fn detects_the_layout_from_nginx_conf_includes() {
+        let debian = "http {\n    include /etc/nginx/conf.d/*.conf;\n    include /etc/nginx/sites-enabled/*;\n}\n";
+        let rhel = "http {\n    include /etc/nginx/conf.d/*.conf;\n}\n";
+
+        let (_root, paths) = root_with(&[("etc/nginx/nginx.conf", debian)], &["etc/nginx/sites-enabled"]);
+        assert_eq!(Layout::detect_in(&paths), Layout::SitesEnabled);
+        // The include of a missing directory doesn't count
+        let (_root, paths) = root_with(&[("etc/nginx/nginx.conf", debian)], &[]);
+        assert_eq!(Layout::detect_in(&paths), Layout::ConfD);
+        let (_root, paths) = root_with(&[("etc/nginx/nginx.conf", rhel)], &["etc/nginx/sites-enabled"]);
+        assert_eq!(Layout::detect_in(&paths), Layout::ConfD);
+    }
+
+    #[test]
+    fn detects_the_layout_from_the_distribution() {
+        for (os_release, layout) in [
+            ("ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n", Layout::ConfD),
+            ("ID=alpine\n", Layout::ConfD),
+            ("ID=linuxmint\nID_LIKE=\"ubuntu debian\"\n", Layout::SitesEnabled),
+        ] {
+            let (_root, paths) = root_with(&[("etc/os-release", os_release)], &[]);
+            assert_eq!(Layout::detect_in(&paths), layout, "{}", os_release);
+        }
+
+        // A sites-enabled directory set up by hand wins over the distribution
+        let (_root, paths) = root_with(&[("etc/os-release", "ID=fedora\n")], &["etc/nginx/sites-enabled"]);
+        assert_eq!(Layout::detect_in(&paths), Layout::SitesEnabled);
+        let (_root, paths) = root_with(&[], &[]);
+        assert_eq!(Layout::detect_in(&paths), Layout::SitesEnabled);
+    }
+
+    #[test]
+    fn conf_d_sites_are_disabled_by_renaming() {
+        let _sandbox = sandbox();
+        let conf_d = Path::new(&paths().conf_d);
+        fs::create_dir_all(conf_d).unwrap();
+        for name in ["a.conf", "b.conf.disabled", "c.conf", "c.conf.disabled", "notes.txt"] {
+            fs::write(conf_d.join(name), "server {}\n").unwrap();
+        }
+
+        let layout = Layout::ConfD;
+        let sites: Vec<(String, String)> = ["a.conf", "b.conf.disabled", "c.conf"]
+            .iter()
+            .map(|file| (file[..1].to_string(), conf_d.join(file).to_string_lossy().to_string()))
+            .collect();
+        assert_eq!(layout.list_sites().unwrap(), sites);
+        assert!(layout.is_enabled("a") && !layout.is_enabled("b"));
+
+        layout.disable_site("a").unwrap();
+        assert!(!layout.is_enabled("a") && conf_d.join("a.conf.disabled").exists());
+        layout.enable_site("b").unwrap();
+        assert!(layout.is_enabled("b") && !conf_d.join("b.conf.disabled").exists());
+        let error = "nginx: [emerg] unknown directive in /etc/nginx/conf.d/b.conf:3";
+        assert_eq!(layout.site_in_error(error), Some("b".to_string()));
+
+        fs::remove_dir_all(conf_d).unwrap();
+    }
+}
@@ -1,4 +1,42 @@
 use colored::*;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When set, human-readable logs go to stderr so stdout only carries the JSON document
+static LOGS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn set_logs_to_stderr(enabled: bool) {
+    LOGS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Write a line to the log stream (stdout, or stderr in JSON output mode)
+fn emit(line: &str) {
+    if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Output stream for child processes whose output is shown to the user
+pub fn log_stdio() -> Stdio {
+    if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+/// Print a label without newline, the rest of the line follows with another log call
+pub fn log_label(message: &str) {
+    if LOGS_TO_STDERR.load(Ordering::Relaxed) {
+        eprint!("{}", message);
+    } else {
+        print!("{}", message);
+        let _ = std::io::stdout().flush();
+    }
+}
 
 /// Helper function to process messages and color the ">" symbol red
 fn process_arrow_message(message: &str, color_fn: impl Fn(&str) -> ColoredString) -> String {
@@ -21,17 +59,17 @@ fn process_arrow_message(message: &str, color_fn: impl Fn(&str) -> ColoredString
 /// Log a message with color
 pub fn log_info(message: &str) {
     let processed = process_arrow_message(message, |s| s.normal());
-    println!("{}", processed);
+    emit(&processed);
 }
 
 pub fn log_success(message: &str) {
     let processed = process_arrow_message(message, |s| s.green());
-    println!("{}", processed);
+    emit(&processed);
 }
 
 pub fn log_warning(message: &str) {
     let processed = process_arrow_message(message, |s| s.yellow());
-    println!("{}", processed);
+    emit(&processed);
 }
 
 pub fn log_error(message: &str) {
@@ -41,5 +79,22 @@ pub fn log_error(message: &str) {
 
 pub fn log_step(message: &str) {
     let processed = process_arrow_message(message, |s| s.blue().bold());
-    println!("{}", processed);
+    emit(&processed);
+}
+
+/// Print a unified diff with added lines in green and removed lines in red
+pub fn log_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            emit(&line.bold().to_string());
+        } else if line.starts_with('+') {
+            emit(&line.green().to_string());
+        } else if line.starts_with('-') {
+            emit(&line.red().to_string());
+        } else if line.starts_with("@@") {
+            emit(&line.cyan().to_string());
+        } else {
+            emit(line);
+        }
+    }
 }
@@ -0,0 +1,111 @@
+use std::fs;
+
+use sha2::{Digest, Sha256};
+
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::layout;
+use crate::mods::models::{Config, DomainConfig};
+
+/// First line of every site file generated by xynginc
+pub const MANAGED_BY_MARKER: &str = "# managed-by: xynginc";
+
+/// Ownership header prepended to a generated site configuration
+pub fn managed_header(config: &DomainConfig) -> Result<String> {
+    Ok(format!(
+        "{}\n# xynginc-version: {}\n# xynginc-config-hash: sha256:{}\n",
+        MANAGED_BY_MARKER,
+        env!("CARGO_PKG_VERSION"),
+        get_config_hash(config)?
+    ))
+}
+
+/// SHA-256 of the domain entry the file was generated from
+pub fn get_config_hash(config: &DomainConfig) -> Result<String> {
+    let serialized = serde_json::to_string(config)
+        .map_err(|e| Error::Other(format!("Failed to serialize config of {}: {}", config.domain, e)))?;
+    Ok(format!("{:x}", Sha256::digest(serialized.as_bytes())))
+}
+
+/// Whether a site file carries the xynginc ownership marker
+pub fn is_managed(content: &str) -> bool {
+    content.lines().next().map(str::trim_end) == Some(MANAGED_BY_MARKER)
+}
+
+/// Sites generated by xynginc (the default site excluded)
+pub fn list_managed_sites() -> Result<Vec<String>> {
+    let mut sites = vec![];
+    for (name, path) in layout().list_sites()? {
+        if name == "default" {
+            continue;
+        }
+
+        // Files that can't be read are never considered ours
+        if let Ok(content) = fs::read_to_string(&path)
+            && is_managed(&content)
+        {
+            sites.push(name);
+        }
+    }
+
+    Ok(sites)
+}
+
+/// Managed sites that are no longer part of the desired config
+pub fn find_stale_sites(config: &Config) -> Result<Vec<String>> {
+    Ok(list_managed_sites()?
+        .into_iter()
+        .filter(|site| !config.domains.iter().any(|d| &d.domain == site))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    fn domain(name: &str) -> DomainConfig {
+        serde_json::from_value(serde_json::json!({ "domain": name, "port": 3000 })).unwrap()
+    }
+
+    #[test]
+    fn only_the_first_line_marks_a_managed_file() {
+        let header = managed_header(&domain("example.com")).unwrap();
+        assert!(is_managed(&format!("{}server {{}}\n", header)));
+        assert!(is_managed("# managed-by: xynginc  \r\nserver {}\n"));
+        assert!(!is_managed(&format!("# my own site\n{}", header)));
+        assert!(!is_managed(""));
+    }
+
+    #[test]
+    fn config_hash_follows_the_domain_entry() {
+        let hash = get_config_hash(&domain("example.com")).unwrap();
+        assert_eq!(hash, get_config_hash(&domain("example.com")).unwrap());
+        assert_ne!(hash, get_config_hash(&domain("api.example.com")).unwrap());
+        assert!(managed_header(&domain("example.com")).unwrap().contains(&format!("sha256:{}\n", hash)));
+    }
+
+    #[test]
+    fn stale_sites_are_the_managed_ones_left_out_of_the_config() {
+        let _sandbox = sandbox();
+        let managed = format!("{}server {{}}\n", managed_header(&domain("example.com")).unwrap());
+        let sites = [
+            ("kept.example.com", managed.as_str()),
+            ("stale.example.com", managed.as_str()),
+            ("manual.example.com", "server {}\n"),
+            ("default", managed.as_str()),
+        ];
+        for (name, content) in sites {
+            fs::write(layout().site_path(name), content).unwrap();
+        }
+
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "domains": [{ "domain": "kept.example.com", "port": 3000 }]
+        }))
+        .unwrap();
+        assert_eq!(find_stale_sites(&config).unwrap(), ["stale.example.com"]);
+
+        for (name, _) in sites {
+            fs::remove_file(layout().site_path(name)).unwrap();
+        }
+    }
+}
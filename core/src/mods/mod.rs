@@ -1,15 +1,30 @@
 // Module declarations
 pub mod apply;
+pub mod archive;
 pub mod backup;
 pub mod check;
 pub mod cleanup;
-pub mod cli;
 pub mod config;
 pub mod constants;
 pub mod domain;
+pub mod error;
+pub mod interpolate;
+pub mod layout;
 pub mod logger;
+pub mod marker;
 pub mod models;
 pub mod nginx;
 pub mod nginx_modules;
+pub mod output;
+pub mod paths;
+pub mod plan;
+pub mod restore;
+pub mod service;
+pub mod snapshot;
 pub mod ssl;
-pub mod utils;
\ No newline at end of file
+pub mod stage;
+pub mod template;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod utils;
+pub mod validate;
\ No newline at end of file
@@ -1,23 +1,300 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::mods::error::{Error, Result};
+
+/// Format of a configuration file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Parse a format name as given on the command line
+    pub fn parse(value: &str) -> Option<ConfigFormat> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Format matching the extension of a file: .json, .yaml/.yml or .toml
+    pub fn from_path(path: &str) -> Option<ConfigFormat> {
+        match Path::new(path).extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Toml => "TOML",
+        }
+    }
+}
+
+/// Desired state of the nginx sites, as read from the JSON, YAML or TOML config
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub domains: Vec<DomainConfig>,
     #[serde(default)]
+    pub domains: Vec<DomainConfig>,
+    /// Other config files whose domains are added to this one: paths or glob
+    /// patterns, relative to this file (e.g. "teams/*.yaml")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Values inherited by the domains of this file and of the files it includes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<DomainDefaults>,
+    #[serde(default, alias = "autoReload")]
     pub auto_reload: bool,
+    /// Read by the XyPriss plugin, which opens ports 80 and 443 when ufw blocks them
+    #[serde(default, alias = "autoFixFirewall", alias = "auto_fix_firewall")]
+    pub autofix_firewall: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Fields a domain inherits from the `defaults` section when it does not set them
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DomainDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssl: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "maxBodySize")]
+    pub max_body_size: Option<String>,
+}
+
+/// Site generated for a single domain
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DomainConfig {
     pub domain: String,
-    pub port: u16,
+    /// Backend port, shorthand for a single-member upstream pool on `host`
+    #[serde(default)]
+    pub port: Option<u16>,
     #[serde(default)]
     pub ssl: bool,
     pub email: Option<String>,
     #[serde(default = "default_host")]
     pub host: String,
-    #[serde(default = "default_max_body_size")]
+    #[serde(default = "default_max_body_size", alias = "maxBodySize")]
     pub max_body_size: String,
+    /// Backend servers of the load-balanced pool (replaces host/port)
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamServer>,
+    #[serde(default, alias = "loadBalancing")]
+    pub load_balancing: LoadBalancing,
+    /// Key used by the `hash` balancing method (e.g. "$request_uri")
+    #[serde(default, alias = "hashKey")]
+    pub hash_key: Option<String>,
+    /// Additional path-based locations, each optionally with its own backend
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    #[serde(default, alias = "basicAuth")]
+    pub basic_auth: Option<BasicAuthConfig>,
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+/// Cross-Origin Resource Sharing headers added to every response of a domain
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Allowed origins, "*" allows any origin
+    #[serde(default = "default_cors_origins", alias = "allowOrigins")]
+    pub allow_origins: Vec<String>,
+    #[serde(default = "default_cors_methods", alias = "allowMethods")]
+    pub allow_methods: Vec<String>,
+    #[serde(default = "default_cors_headers", alias = "allowHeaders")]
+    pub allow_headers: Vec<String>,
+    #[serde(default, alias = "allowCredentials")]
+    pub allow_credentials: bool,
+    /// Preflight cache duration in seconds
+    #[serde(default = "default_cors_max_age", alias = "maxAge")]
+    pub max_age: u32,
+}
+
+/// HTTP basic authentication protecting a whole domain
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthConfig {
+    #[serde(default = "default_auth_realm")]
+    pub realm: String,
+    /// Path to an htpasswd file, read by nginx. Write the path itself:
+    /// `${file:...}` would put the content of the file here instead
+    #[serde(alias = "userFile")]
+    pub user_file: String,
+}
+
+/// Proxy response caching for the main location of a domain
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Validity of cached 200/301/302 responses (e.g. "10m")
+    #[serde(default = "default_cache_valid")]
+    pub valid: String,
+    #[serde(default = "default_cache_max_size", alias = "maxSize")]
+    pub max_size: String,
+    /// Entries not accessed during this time are removed (e.g. "60m")
+    #[serde(default = "default_cache_inactive")]
+    pub inactive: String,
+}
+
+/// A path-based location mapped to a backend
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Path prefix (e.g. "/api") or regular expression when `regex` is set
+    pub path: String,
+    #[serde(default)]
+    pub regex: bool,
+    /// Backend port; the route uses the domain pool when neither port nor upstreams are set
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Host of `port`, the `host` of the domain when not set
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamServer>,
+    #[serde(default, alias = "loadBalancing")]
+    pub load_balancing: LoadBalancing,
+    #[serde(default, alias = "hashKey")]
+    pub hash_key: Option<String>,
+    /// Remove the route prefix before proxying (e.g. /api/users -> /users)
+    #[serde(default, alias = "stripPrefix")]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub rewrite: Option<RouteRewrite>,
+    #[serde(default, alias = "maxBodySize")]
+    pub max_body_size: Option<String>,
+    #[serde(default, alias = "connectTimeout")]
+    pub connect_timeout: Option<String>,
+    #[serde(default, alias = "sendTimeout")]
+    pub send_timeout: Option<String>,
+    #[serde(default, alias = "readTimeout")]
+    pub read_timeout: Option<String>,
+}
+
+/// Path rewrite applied before proxying, rendered as `rewrite <pattern> <replacement> break;`
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRewrite {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// A single member of a domain's upstream pool
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamServer {
+    #[serde(default = "default_host")]
+    pub host: String,
+    pub port: u16,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default = "default_max_fails", alias = "maxFails")]
+    pub max_fails: u32,
+    #[serde(default = "default_fail_timeout", alias = "failTimeout")]
+    pub fail_timeout: String,
+    #[serde(default)]
+    pub backup: bool,
+}
+
+/// Load balancing method of an upstream pool
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    #[default]
+    #[serde(alias = "roundRobin")]
+    RoundRobin,
+    #[serde(alias = "leastConn")]
+    LeastConn,
+    #[serde(alias = "ipHash")]
+    IpHash,
+    Hash,
+}
+
+impl DomainDefaults {
+    /// These defaults, completed by the ones of an including file
+    pub fn or(&self, parent: &DomainDefaults) -> DomainDefaults {
+        DomainDefaults {
+            ssl: self.ssl.or(parent.ssl),
+            email: self.email.clone().or_else(|| parent.email.clone()),
+            host: self.host.clone().or_else(|| parent.host.clone()),
+            max_body_size: self.max_body_size.clone().or_else(|| parent.max_body_size.clone()),
+        }
+    }
+}
+
+impl DomainConfig {
+    /// Resolve the upstream pool of this domain.
+    /// The legacy `host`/`port` pair is turned into a one-member pool.
+    pub fn upstream_servers(&self) -> Result<Vec<UpstreamServer>> {
+        match (self.port, self.upstreams.is_empty()) {
+            (Some(_), false) => Err(Error::InvalidConfig(format!(
+                "{}: 'port' and 'upstreams' are mutually exclusive",
+                self.domain
+            ))),
+            (None, true) => Err(Error::InvalidConfig(format!(
+                "{}: either 'port' or 'upstreams' must be set",
+                self.domain
+            ))),
+            (Some(port), true) => Ok(vec![UpstreamServer::single(&self.host, port)]),
+            (None, false) => Ok(self.upstreams.clone()),
+        }
+    }
+}
+
+impl RouteConfig {
+    /// Resolve the dedicated upstream pool of this route, a route with only a
+    /// `port` using `domain_host` (the `host` of its domain).
+    /// Returns `None` when the route is served by the domain pool.
+    pub fn upstream_servers(&self, domain_host: &str) -> Result<Option<Vec<UpstreamServer>>> {
+        match (self.port, self.upstreams.is_empty()) {
+            (Some(_), false) => Err(Error::InvalidConfig(format!(
+                "route {}: 'port' and 'upstreams' are mutually exclusive",
+                self.path
+            ))),
+            (Some(port), true) => Ok(Some(vec![UpstreamServer::single(
+                self.host.as_deref().unwrap_or(domain_host),
+                port,
+            )])),
+            (None, false) => Ok(Some(self.upstreams.clone())),
+            (None, true) if self.host.is_some() => Err(Error::InvalidConfig(format!(
+                "route {}: 'host' requires a 'port'",
+                self.path
+            ))),
+            (None, true) => Ok(None),
+        }
+    }
+}
+
+impl UpstreamServer {
+    /// One-member pool entry with default balancing parameters
+    pub fn single(host: &str, port: u16) -> Self {
+        UpstreamServer {
+            host: host.to_string(),
+            port,
+            weight: default_weight(),
+            max_fails: default_max_fails(),
+            fail_timeout: default_fail_timeout(),
+            backup: false,
+        }
+    }
 }
 
 fn default_max_body_size() -> String {
@@ -27,3 +304,50 @@ fn default_max_body_size() -> String {
 fn default_host() -> String {
     "localhost".to_string()
 }
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_max_fails() -> u32 {
+    1
+}
+
+fn default_fail_timeout() -> String {
+    "10s".to_string()
+}
+
+fn default_cors_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+
+fn default_cors_headers() -> Vec<String> {
+    vec!["Authorization".to_string(), "Content-Type".to_string()]
+}
+
+fn default_cors_max_age() -> u32 {
+    86400
+}
+
+fn default_auth_realm() -> String {
+    "Restricted".to_string()
+}
+
+fn default_cache_valid() -> String {
+    "10m".to_string()
+}
+
+fn default_cache_max_size() -> String {
+    "1g".to_string()
+}
+
+fn default_cache_inactive() -> String {
+    "60m".to_string()
+}
@@ -1,29 +1,56 @@
-use std::process::Command;
+use serde::Serialize;
+use std::process::{Command, Output};
 
 use crate::mods::backup::list_backups;
-use crate::mods::logger::{log_error, log_info, log_step, log_success, log_warning};
+use crate::mods::domain::DomainInfo;
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::{log_error, log_info, log_label, log_step, log_success, log_warning};
 use crate::mods::nginx_modules;
+use crate::mods::output::CommandReport;
+use crate::mods::paths::{paths, Paths};
+use crate::mods::service::{service_manager, ServiceKind};
 
-pub fn test_nginx() -> Result<(), String> {
-    let output = Command::new("nginx")
-        .arg("-t")
-        .output()
-        .map_err(|e| format!("Failed to run nginx -t: {}", e))?;
+/// Run `nginx -t` against the live configuration
+pub fn test_nginx() -> Result<()> {
+    test_nginx_config(live_config())
+}
+
+/// Run `nginx -t` against the given main config file (e.g. a staged tree), or
+/// the live one. Nothing is installed or changed on the system.
+pub fn test_nginx_config(conf_path: Option<&str>) -> Result<()> {
+    let output = run_nginx_test(conf_path)
+        .map_err(Error::io("Failed to run nginx -t"))?;
 
     if output.status.success() {
         Ok(())
     } else {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Nginx config test failed:\n{}", stderr))
+        Err(Error::NginxTest(format!("Nginx config test failed:\n{}", stderr)))
     }
 }
 
+/// Main config file to pass to nginx with `-c`, when the paths differ from
+/// the defaults nginx was built with
+pub fn live_config() -> Option<&'static str> {
+    let nginx_conf = &paths().nginx_conf;
+    (*nginx_conf != Paths::default().nginx_conf).then_some(nginx_conf.as_str())
+}
+
+/// Run `nginx -t`, against the given main config file or the live one
+pub fn run_nginx_test(conf_path: Option<&str>) -> std::io::Result<Output> {
+    let mut command = Command::new("nginx");
+    command.arg("-t");
+    if let Some(path) = conf_path {
+        command.args(["-c", path]);
+    }
+    command.output()
+}
+
 /// Test nginx configuration and auto-fix module errors
-pub fn test_nginx_with_autofix() -> Result<(), String> {
-    let output = Command::new("nginx")
-        .arg("-t")
-        .output()
-        .map_err(|e| format!("Failed to run nginx -t: {}", e))?;
+/// `conf_path` selects another main config file (e.g. a staged tree)
+pub fn test_nginx_with_autofix(conf_path: Option<&str>) -> Result<()> {
+    let output = run_nginx_test(conf_path)
+        .map_err(Error::io("Failed to run nginx -t"))?;
 
     if output.status.success() {
         return Ok(());
@@ -41,67 +68,74 @@ pub fn test_nginx_with_autofix() -> Result<(), String> {
                 log_success("✓ Module installed, retesting configuration...");
                 
                 // Retry nginx test
-                let retry_output = Command::new("nginx")
-                    .arg("-t")
-                    .output()
-                    .map_err(|e| format!("Failed to retest nginx: {}", e))?;
+                let retry_output = run_nginx_test(conf_path)
+                    .map_err(Error::io("Failed to retest nginx"))?;
                 
                 if retry_output.status.success() {
                     log_success("✓ Configuration is now valid!");
                     return Ok(());
                 } else {
                     let retry_stderr = String::from_utf8_lossy(&retry_output.stderr);
-                    return Err(format!("Nginx config test still failed after module installation:\n{}", retry_stderr));
+                    return Err(Error::NginxTest(format!(
+                        "Nginx config test still failed after module installation:\n{}",
+                        retry_stderr
+                    )));
                 }
             }
             Err(e) => {
-                return Err(format!("Failed to install headers-more module: {}\nOriginal nginx error:\n{}", e, stderr));
+                return Err(Error::NginxTest(format!(
+                    "Failed to install headers-more module: {}\nOriginal nginx error:\n{}",
+                    e, stderr
+                )));
             }
         }
     }
     
     // If not a module error, return the original error
-    Err(format!("Nginx config test failed:\n{}", stderr))
+    Err(Error::NginxTest(format!("Nginx config test failed:\n{}", stderr)))
 }
 
-pub fn reload_nginx() -> Result<(), String> {
-    let output = Command::new("systemctl")
-        .args(&["reload", "nginx"])
-        .output()
-        .map_err(|e| format!("Failed to reload nginx: {}", e))?;
+/// Reload nginx through the service manager
+pub fn reload_nginx() -> Result<()> {
+    service_manager().reload()?;
+    log_success("✓ Nginx reloaded successfully!");
+    Ok(())
+}
 
-    if output.status.success() {
-        log_success("✓ Nginx reloaded successfully!");
-        Ok(())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Failed to reload nginx:\n{}", stderr))
-    }
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub service_manager: ServiceKind,
+    pub nginx_active: bool,
+    pub config_valid: bool,
+    pub config_error: Option<Error>,
+    /// Backup identifiers, most recent first
+    pub backups: Vec<String>,
+    pub domains: Vec<DomainInfo>,
 }
 
-pub fn show_status() -> Result<(), String> {
+impl CommandReport for StatusReport {}
+
+pub fn show_status() -> Result<StatusReport> {
     use crate::mods::domain::list_domains;
     
     log_step(" XyNginC Status\n");
 
     // Nginx status
-    print!("Nginx service: ");
-    let output = Command::new("systemctl")
-        .args(&["is-active", "nginx"])
-        .output()
-        .map_err(|e| format!("Failed to check nginx status: {}", e))?;
-
-    if output.status.success() {
-        log_success("✓ active");
+    log_label("Nginx service: ");
+    let manager = service_manager();
+    let nginx_active = manager.is_active()?;
+    if nginx_active {
+        log_success(&format!("✓ active ({})", manager.kind().name()));
     } else {
-        log_info("◯ inactive");
+        log_info(&format!("◯ inactive ({})", manager.kind().name()));
     }
 
     // Configuration test
-    print!("Configuration: ");
-    match test_nginx() {
-        Ok(_) => log_success("✓ valid"),
-        Err(_) => log_error("❌ invalid"),
+    log_label("Configuration: ");
+    let config_error = test_nginx().err();
+    match config_error {
+        None => log_success("✓ valid"),
+        Some(_) => log_error("❌ invalid"),
     }
 
     // List backups
@@ -113,7 +147,14 @@ pub fn show_status() -> Result<(), String> {
 
     // List domains
     log_info("\nConfigured domains:");
-    list_domains()?;
+    let domains = list_domains()?.domains;
 
-    Ok(())
+    Ok(StatusReport {
+        service_manager: manager.kind(),
+        nginx_active,
+        config_valid: config_error.is_none(),
+        config_error,
+        backups,
+        domains,
+    })
 }
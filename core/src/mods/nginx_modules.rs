@@ -1,4 +1,4 @@
-/**
+/*!
  * Nginx Modules Management
  * 
  * This module handles the installation and configuration of additional nginx modules
@@ -9,13 +9,16 @@ use std::fs;
 use std::path::Path;
 use std::process::{Command, Stdio};
 
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::{log_info, log_stdio};
+use crate::mods::nginx::{live_config, run_nginx_test};
+use crate::mods::paths::paths;
+
 /// Check if headers-more module is installed and loaded
-pub fn check_headers_more_module() -> Result<bool, String> {
+pub fn check_headers_more_module() -> Result<bool> {
     // Check if the module file exists
-    let module_paths = vec![
-        "/usr/share/nginx/modules/ngx_http_headers_more_filter_module.so",
-        "/usr/lib/nginx/modules/ngx_http_headers_more_filter_module.so",
-    ];
+    let module_paths = ["/usr/share/nginx/modules/ngx_http_headers_more_filter_module.so",
+        "/usr/lib/nginx/modules/ngx_http_headers_more_filter_module.so"];
     
     let module_exists = module_paths.iter().any(|path| Path::new(path).exists());
     
@@ -24,10 +27,10 @@ pub fn check_headers_more_module() -> Result<bool, String> {
     }
     
     // Check if module is loaded in nginx.conf
-    let nginx_conf = "/etc/nginx/nginx.conf";
+    let nginx_conf = &paths().nginx_conf;
     if Path::new(nginx_conf).exists() {
         let content = fs::read_to_string(nginx_conf)
-            .map_err(|e| format!("Failed to read nginx.conf: {}", e))?;
+            .map_err(Error::io("Failed to read nginx.conf"))?;
         
         if content.contains("ngx_http_headers_more_filter_module.so") {
             return Ok(true);
@@ -38,11 +41,11 @@ pub fn check_headers_more_module() -> Result<bool, String> {
 }
 
 /// Get nginx version
-fn get_nginx_version() -> Result<String, String> {
+fn get_nginx_version() -> Result<String> {
     let output = Command::new("nginx")
         .arg("-v")
         .output()
-        .map_err(|e| format!("Failed to get nginx version: {}", e))?;
+        .map_err(Error::io("Failed to get nginx version"))?;
     
     let version_output = String::from_utf8_lossy(&output.stderr);
     
@@ -51,15 +54,15 @@ fn get_nginx_version() -> Result<String, String> {
         .split('/')
         .nth(1)
         .and_then(|v| v.split_whitespace().next())
-        .ok_or("Failed to parse nginx version")?
+        .ok_or_else(|| Error::Command("Failed to parse nginx version".to_string()))?
         .to_string();
     
     Ok(version)
 }
 
 /// Install build dependencies
-fn install_build_dependencies() -> Result<(), String> {
-    println!("   → Installing build dependencies...");
+fn install_build_dependencies() -> Result<()> {
+    log_info("   → Installing build dependencies...");
     
     let packages = vec![
         "build-essential",
@@ -80,32 +83,32 @@ fn install_build_dependencies() -> Result<(), String> {
     
     let output = cmd
         .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
+        .stdout(log_stdio())
         .stderr(Stdio::inherit())
         .output()
-        .map_err(|e| format!("Failed to install build dependencies: {}", e))?;
+        .map_err(Error::io("Failed to install build dependencies"))?;
     
     if !output.status.success() {
-        return Err("Failed to install build dependencies".to_string());
+        return Err(Error::Command("Failed to install build dependencies".to_string()));
     }
     
-    println!("   ✓ Build dependencies installed");
+    log_info("   ✓ Build dependencies installed");
     Ok(())
 }
 
 /// Download and compile headers-more module
-pub fn install_headers_more_module() -> Result<(), String> {
-    println!("\n> Installing headers-more-nginx-module...\n");
+pub fn install_headers_more_module() -> Result<()> {
+    log_info("\n> Installing headers-more-nginx-module...\n");
     
     // Check if already installed
     if check_headers_more_module()? {
-        println!("   ✓ headers-more module already installed and configured");
+        log_info("   ✓ headers-more module already installed and configured");
         return Ok(());
     }
     
     // Get nginx version
     let nginx_version = get_nginx_version()?;
-    println!("   → Detected nginx version: {}", nginx_version);
+    log_info(&format!("   → Detected nginx version: {}", nginx_version));
     
     // Install build dependencies
     install_build_dependencies()?;
@@ -113,47 +116,47 @@ pub fn install_headers_more_module() -> Result<(), String> {
     // Create build directory
     let build_dir = "/usr/local/src/xynginc-build";
     fs::create_dir_all(build_dir)
-        .map_err(|e| format!("Failed to create build directory: {}", e))?;
+        .map_err(Error::io("Failed to create build directory"))?;
     
     // Download nginx source
-    println!("   → Downloading nginx source...");
+    log_info("   → Downloading nginx source...");
     let nginx_tar = format!("nginx-{}.tar.gz", nginx_version);
     let nginx_url = format!("http://nginx.org/download/{}", nginx_tar);
     
     let download_output = Command::new("wget")
-        .args(&["-q", "-O", &format!("{}/{}", build_dir, nginx_tar), &nginx_url])
+        .args(["-q", "-O", &format!("{}/{}", build_dir, nginx_tar), &nginx_url])
         .current_dir(build_dir)
         .output()
-        .map_err(|e| format!("Failed to download nginx source: {}", e))?;
+        .map_err(Error::io("Failed to download nginx source"))?;
     
     if !download_output.status.success() {
-        return Err(format!("Failed to download nginx source from {}", nginx_url));
+        return Err(Error::Command(format!("Failed to download nginx source from {}", nginx_url)));
     }
     
     // Extract nginx source
-    println!("   → Extracting nginx source...");
+    log_info("   → Extracting nginx source...");
     let extract_output = Command::new("tar")
-        .args(&["-xzf", &nginx_tar])
+        .args(["-xzf", &nginx_tar])
         .current_dir(build_dir)
         .output()
-        .map_err(|e| format!("Failed to extract nginx source: {}", e))?;
+        .map_err(Error::io("Failed to extract nginx source"))?;
     
     if !extract_output.status.success() {
-        return Err("Failed to extract nginx source".to_string());
+        return Err(Error::Command("Failed to extract nginx source".to_string()));
     }
     
     // Clone headers-more module
-    println!("   → Cloning headers-more-nginx-module...");
+    log_info("   → Cloning headers-more-nginx-module...");
     let module_dir = format!("{}/headers-more-nginx-module", build_dir);
     
     // Remove if exists
     if Path::new(&module_dir).exists() {
         fs::remove_dir_all(&module_dir)
-            .map_err(|e| format!("Failed to remove old module directory: {}", e))?;
+            .map_err(Error::io("Failed to remove old module directory"))?;
     }
     
     let clone_output = Command::new("git")
-        .args(&[
+        .args([
             "clone",
             "--quiet",
             "https://github.com/openresty/headers-more-nginx-module.git",
@@ -161,18 +164,18 @@ pub fn install_headers_more_module() -> Result<(), String> {
         ])
         .current_dir(build_dir)
         .output()
-        .map_err(|e| format!("Failed to clone headers-more module: {}", e))?;
+        .map_err(Error::io("Failed to clone headers-more module"))?;
     
     if !clone_output.status.success() {
-        return Err("Failed to clone headers-more module".to_string());
+        return Err(Error::Command("Failed to clone headers-more module".to_string()));
     }
     
     // Configure and compile module
-    println!("   → Compiling module (this may take a moment)...");
+    log_info("   → Compiling module (this may take a moment)...");
     let nginx_src_dir = format!("{}/nginx-{}", build_dir, nginx_version);
     
     let configure_output = Command::new("./configure")
-        .args(&[
+        .args([
             "--with-compat",
             &format!("--add-dynamic-module={}", module_dir),
         ])
@@ -180,11 +183,11 @@ pub fn install_headers_more_module() -> Result<(), String> {
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .output()
-        .map_err(|e| format!("Failed to configure nginx: {}", e))?;
+        .map_err(Error::io("Failed to configure nginx"))?;
     
     if !configure_output.status.success() {
         let stderr = String::from_utf8_lossy(&configure_output.stderr);
-        return Err(format!("Failed to configure nginx: {}", stderr));
+        return Err(Error::Command(format!("Failed to configure nginx: {}", stderr)));
     }
     
     let make_output = Command::new("make")
@@ -193,19 +196,19 @@ pub fn install_headers_more_module() -> Result<(), String> {
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .output()
-        .map_err(|e| format!("Failed to compile module: {}", e))?;
+        .map_err(Error::io("Failed to compile module"))?;
     
     if !make_output.status.success() {
         let stderr = String::from_utf8_lossy(&make_output.stderr);
-        return Err(format!("Failed to compile module: {}", stderr));
+        return Err(Error::Command(format!("Failed to compile module: {}", stderr)));
     }
     
     // Install module
-    println!("   → Installing module...");
+    log_info("   → Installing module...");
     let module_file = format!("{}/objs/ngx_http_headers_more_filter_module.so", nginx_src_dir);
     
     if !Path::new(&module_file).exists() {
-        return Err("Compiled module not found".to_string());
+        return Err(Error::Command("Compiled module not found".to_string()));
     }
     
     // Create modules directory (handle symlinks)
@@ -227,46 +230,46 @@ pub fn install_headers_more_module() -> Result<(), String> {
     
     // Create the actual directory
     fs::create_dir_all(&real_modules_dir)
-        .map_err(|e| format!("Failed to create modules directory: {}", e))?;
+        .map_err(Error::io("Failed to create modules directory"))?;
     
     // Determine the final module path
     let module_dest = real_modules_dir.join("ngx_http_headers_more_filter_module.so");
     
     // Copy module
     fs::copy(&module_file, &module_dest)
-        .map_err(|e| format!("Failed to copy module: {}", e))?;
+        .map_err(Error::io("Failed to copy module"))?;
     
-    println!("   ✓ Module compiled and installed");
+    log_info("   ✓ Module compiled and installed");
     
     // Configure nginx to load the module
     configure_nginx_module()?;
     
     // Cleanup build directory
-    println!("   → Cleaning up build files...");
+    log_info("   → Cleaning up build files...");
     fs::remove_dir_all(build_dir)
-        .map_err(|e| format!("Failed to cleanup build directory: {}", e))?;
+        .map_err(Error::io("Failed to cleanup build directory"))?;
     
-    println!("\n✅ headers-more module installed successfully!");
+    log_info("\n✅ headers-more module installed successfully!");
     
     Ok(())
 }
 
 /// Configure nginx.conf to load the headers-more module
-fn configure_nginx_module() -> Result<(), String> {
-    println!("   → Configuring nginx to load module...");
+fn configure_nginx_module() -> Result<()> {
+    log_info("   → Configuring nginx to load module...");
     
-    let nginx_conf = "/etc/nginx/nginx.conf";
+    let nginx_conf = &paths().nginx_conf;
     
     if !Path::new(nginx_conf).exists() {
-        return Err("nginx.conf not found".to_string());
+        return Err(Error::Command("nginx.conf not found".to_string()));
     }
     
     let content = fs::read_to_string(nginx_conf)
-        .map_err(|e| format!("Failed to read nginx.conf: {}", e))?;
+        .map_err(Error::io("Failed to read nginx.conf"))?;
     
     // Check if module is already loaded
     if content.contains("ngx_http_headers_more_filter_module.so") {
-        println!("   ✓ Module already configured in nginx.conf");
+        log_info("   ✓ Module already configured in nginx.conf");
         return Ok(());
     }
     
@@ -297,23 +300,21 @@ fn configure_nginx_module() -> Result<(), String> {
     
     // Write updated configuration
     fs::write(nginx_conf, new_content)
-        .map_err(|e| format!("Failed to write nginx.conf: {}", e))?;
+        .map_err(Error::io("Failed to write nginx.conf"))?;
     
-    println!("   ✓ Module configured in nginx.conf");
+    log_info("   ✓ Module configured in nginx.conf");
     
     // Test nginx configuration
-    println!("   → Testing nginx configuration...");
-    let test_output = Command::new("nginx")
-        .arg("-t")
-        .output()
-        .map_err(|e| format!("Failed to test nginx configuration: {}", e))?;
+    log_info("   → Testing nginx configuration...");
+    let test_output = run_nginx_test(live_config())
+        .map_err(Error::io("Failed to test nginx configuration"))?;
     
     if !test_output.status.success() {
         let stderr = String::from_utf8_lossy(&test_output.stderr);
-        return Err(format!("Nginx configuration test failed: {}", stderr));
+        return Err(Error::Command(format!("Nginx configuration test failed: {}", stderr)));
     }
     
-    println!("   ✓ Nginx configuration test passed");
+    log_info("   ✓ Nginx configuration test passed");
     
     Ok(())
 }
@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::log_error;
+
+/// How a command reports its outcome
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Logs only
+    Text,
+    /// A single JSON document on stdout, logs on stderr
+    Json,
+}
+
+impl OutputFormat {
+    /// Parse a format name as given on the command line
+    pub fn parse(value: &str) -> Option<OutputFormat> {
+        match value {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Data returned by a command, printed as the `data` field with `--output json`
+pub trait CommandReport: Serialize {
+    /// Error of a command that ran to completion without fully succeeding
+    /// (e.g. some domains of an apply failed)
+    fn failure(&self) -> Option<Error> {
+        None
+    }
+}
+
+impl CommandReport for () {}
+
+/// Single document written to stdout with `--output json`
+#[derive(Serialize)]
+struct JsonDocument<'a, T> {
+    command: &'a str,
+    success: bool,
+    data: Option<&'a T>,
+    error: Option<&'a Error>,
+}
+
+/// Print the outcome of a command in the selected format and return the process exit code
+pub fn finish<T: CommandReport>(format: OutputFormat, command: &str, result: Result<T>) -> i32 {
+    let (data, error) = match result {
+        Ok(report) => {
+            let failure = report.failure();
+            (Some(report), failure)
+        }
+        Err(e) => (None, Some(e)),
+    };
+
+    match format {
+        OutputFormat::Text => {
+            if let Some(e) = &error {
+                log_error(&format!("❌ Error: {}", e));
+            }
+        }
+        OutputFormat::Json => {
+            let document = JsonDocument {
+                command,
+                success: error.is_none(),
+                data: data.as_ref(),
+                error: error.as_ref(),
+            };
+            match serde_json::to_string_pretty(&document) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    log_error(&format!("❌ Error: Failed to serialize output: {}", e));
+                    return 1;
+                }
+            }
+        }
+    }
+
+    error.map_or(0, |e| e.exit_code())
+}
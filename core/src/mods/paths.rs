@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::mods::backup::BackupSettings;
+use crate::mods::backup::Compression;
+use crate::mods::constants::SETTINGS_PATH;
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::Layout;
+use crate::mods::service::ServiceKind;
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+/// Filesystem locations used by xynginc.
+///
+/// Resolved once at startup by `Paths::load`, from the defaults below, then the
+/// `[paths]` table of /etc/xynginc/xynginc.toml, then `XYNGINC_*` environment
+/// variables. Every path is finally prefixed with the root directory
+/// (`--root` or `XYNGINC_ROOT`), which lets xynginc run against a sandbox.
+/// The site layout and the service manager are read from the same sources
+/// (`layout`/`XYNGINC_LAYOUT`, `service_manager`/`XYNGINC_SERVICE_MANAGER`),
+/// as well as the `[backup]` retention and compression settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct Paths {
+    /// Prefix of every other path, empty for the real filesystem
+    pub root: String,
+    pub nginx_dir: String,
+    pub nginx_conf: String,
+    pub sites_available: String,
+    pub sites_enabled: String,
+    /// Site directory of the conf.d layout
+    pub conf_d: String,
+    pub backup_dir: String,
+    pub staging_dir: String,
+    pub letsencrypt_live_dir: String,
+    pub template_dir: String,
+    /// Web root holding the index page and the errors/ pages
+    pub web_root: String,
+    /// Root served for the ACME challenges of certificate renewals
+    pub acme_webroot: String,
+    /// Parent directory of the per-domain proxy caches
+    pub cache_dir: String,
+    /// PID file of the nginx master, used by the signal service manager
+    pub pid_file: String,
+    /// Site layout forced by the settings, detected when unset
+    pub layout: Option<Layout>,
+    /// Service manager forced by the settings, detected when unset
+    pub service_manager: Option<ServiceKind>,
+    pub backup: BackupSettings,
+}
+
+/// Overrides read from the settings file or the environment
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PathOverrides {
+    nginx_dir: Option<String>,
+    nginx_conf: Option<String>,
+    sites_available: Option<String>,
+    sites_enabled: Option<String>,
+    conf_d: Option<String>,
+    backup_dir: Option<String>,
+    staging_dir: Option<String>,
+    letsencrypt_live_dir: Option<String>,
+    template_dir: Option<String>,
+    web_root: Option<String>,
+    acme_webroot: Option<String>,
+    cache_dir: Option<String>,
+    pid_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    paths: PathOverrides,
+    layout: Option<Layout>,
+    service_manager: Option<ServiceKind>,
+    backup: BackupSettings,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths::resolve(String::new(), Settings::default())
+    }
+}
+
+impl Paths {
+    /// Resolve the paths from the settings file and the environment.
+    /// `root` takes precedence over `XYNGINC_ROOT`.
+    pub fn load(root: Option<&str>) -> Result<Paths> {
+        let root = root
+            .map(str::to_string)
+            .or_else(|| env::var("XYNGINC_ROOT").ok())
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+
+        let settings_path = env::var("XYNGINC_SETTINGS").unwrap_or_else(|_| format!("{}{}", root, SETTINGS_PATH));
+        let mut settings = if Path::new(&settings_path).exists() {
+            let content = fs::read_to_string(&settings_path)
+                .map_err(Error::io(&format!("Failed to read {}", settings_path)))?;
+            toml::from_str::<Settings>(&content)
+                .map_err(|e| Error::ConfigParse(format!("Invalid settings file {}: {}", settings_path, e)))?
+        } else {
+            Settings::default()
+        };
+
+        let overrides = &mut settings.paths;
+        for (var, field) in [
+            ("XYNGINC_NGINX_DIR", &mut overrides.nginx_dir),
+            ("XYNGINC_NGINX_CONF", &mut overrides.nginx_conf),
+            ("XYNGINC_SITES_AVAILABLE", &mut overrides.sites_available),
+            ("XYNGINC_SITES_ENABLED", &mut overrides.sites_enabled),
+            ("XYNGINC_CONF_D", &mut overrides.conf_d),
+            ("XYNGINC_BACKUP_DIR", &mut overrides.backup_dir),
+            ("XYNGINC_STAGING_DIR", &mut overrides.staging_dir),
+            ("XYNGINC_LETSENCRYPT_LIVE_DIR", &mut overrides.letsencrypt_live_dir),
+            ("XYNGINC_TEMPLATE_DIR", &mut overrides.template_dir),
+            ("XYNGINC_WEB_ROOT", &mut overrides.web_root),
+            ("XYNGINC_ACME_WEBROOT", &mut overrides.acme_webroot),
+            ("XYNGINC_CACHE_DIR", &mut overrides.cache_dir),
+            ("XYNGINC_PID_FILE", &mut overrides.pid_file),
+        ] {
+            if let Ok(value) = env::var(var) {
+                *field = Some(value);
+            }
+        }
+
+        if let Ok(value) = env::var("XYNGINC_LAYOUT") {
+            settings.layout = Some(Layout::parse(&value).ok_or_else(|| {
+                Error::ConfigParse(format!("Invalid XYNGINC_LAYOUT '{}', expected sites-enabled or conf.d", value))
+            })?);
+        }
+
+        if let Ok(value) = env::var("XYNGINC_SERVICE_MANAGER") {
+            settings.service_manager = Some(ServiceKind::parse(&value).ok_or_else(|| {
+                Error::ConfigParse(format!(
+                    "Invalid XYNGINC_SERVICE_MANAGER '{}', expected systemd, openrc, sysv or signal",
+                    value
+                ))
+            })?);
+        }
+
+        let backup = &mut settings.backup;
+        for (var, field) in [
+            ("XYNGINC_BACKUP_KEEP_LAST", &mut backup.keep_last),
+            ("XYNGINC_BACKUP_KEEP_DAILY", &mut backup.keep_daily),
+        ] {
+            if let Ok(value) = env::var(var) {
+                *field = value
+                    .parse()
+                    .map_err(|_| Error::ConfigParse(format!("Invalid {} '{}', expected a number", var, value)))?;
+            }
+        }
+
+        if let Ok(value) = env::var("XYNGINC_BACKUP_COMPRESSION") {
+            backup.compression = Compression::parse(&value).ok_or_else(|| {
+                Error::ConfigParse(format!(
+                    "Invalid XYNGINC_BACKUP_COMPRESSION '{}', expected none, gzip or zstd",
+                    value
+                ))
+            })?;
+        }
+
+        let paths = Paths::resolve(root, settings);
+        // apply and restore stage their changes in a copy of nginx_dir
+        if Path::new(&paths.nginx_conf).strip_prefix(&paths.nginx_dir).is_err() {
+            return Err(Error::ConfigParse(format!(
+                "nginx_conf ({}) must be inside nginx_dir ({}), changes are staged and tested in a copy of that directory",
+                paths.nginx_conf, paths.nginx_dir
+            )));
+        }
+        Ok(paths)
+    }
+
+    fn resolve(root: String, settings: Settings) -> Paths {
+        let overrides = settings.paths;
+        let nginx_dir = overrides.nginx_dir.unwrap_or_else(|| "/etc/nginx".to_string());
+        let nginx_dir = nginx_dir.trim_end_matches('/');
+        let prefixed = |path: String| format!("{}{}", root, path);
+
+        Paths {
+            nginx_conf: prefixed(overrides.nginx_conf.unwrap_or_else(|| format!("{}/nginx.conf", nginx_dir))),
+            sites_available: prefixed(
+                overrides.sites_available.unwrap_or_else(|| format!("{}/sites-available", nginx_dir)),
+            ),
+            sites_enabled: prefixed(overrides.sites_enabled.unwrap_or_else(|| format!("{}/sites-enabled", nginx_dir))),
+            conf_d: prefixed(overrides.conf_d.unwrap_or_else(|| format!("{}/conf.d", nginx_dir))),
+            backup_dir: prefixed(overrides.backup_dir.unwrap_or_else(|| "/var/backups/xynginc".to_string())),
+            staging_dir: prefixed(overrides.staging_dir.unwrap_or_else(|| "/var/lib/xynginc/staging".to_string())),
+            letsencrypt_live_dir: prefixed(
+                overrides.letsencrypt_live_dir.unwrap_or_else(|| "/etc/letsencrypt/live".to_string()),
+            ),
+            template_dir: prefixed(overrides.template_dir.unwrap_or_else(|| "/etc/xynginc/templates".to_string())),
+            web_root: prefixed(overrides.web_root.unwrap_or_else(|| "/var/www/html".to_string())),
+            acme_webroot: prefixed(overrides.acme_webroot.unwrap_or_else(|| "/var/www/letsencrypt".to_string())),
+            cache_dir: prefixed(overrides.cache_dir.unwrap_or_else(|| "/var/cache/nginx".to_string())),
+            pid_file: prefixed(overrides.pid_file.unwrap_or_else(|| "/run/nginx.pid".to_string())),
+            nginx_dir: prefixed(nginx_dir.to_string()),
+            layout: settings.layout,
+            service_manager: settings.service_manager,
+            backup: settings.backup,
+            root,
+        }
+    }
+
+    /// Path as seen by nginx at runtime, i.e. without the root prefix
+    pub fn unrooted<'a>(&self, path: &'a str) -> &'a str {
+        if self.root.is_empty() {
+            return path;
+        }
+        path.strip_prefix(self.root.as_str()).unwrap_or(path)
+    }
+}
+
+/// Install the paths used by the rest of the process; only the first call wins
+pub fn init_paths(paths: Paths) -> Result<()> {
+    PATHS
+        .set(paths)
+        .map_err(|_| Error::Other("Paths are already initialized".to_string()))
+}
+
+/// Paths of the process, the defaults if `init_paths` was never called
+pub fn paths() -> &'static Paths {
+    PATHS.get_or_init(Paths::default)
+}
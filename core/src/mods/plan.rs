@@ -0,0 +1,343 @@
+use serde::Serialize;
+use std::fs;
+
+use crate::mods::apply::read_config;
+use crate::mods::cleanup::detect_broken_configs;
+use crate::mods::config::{config_exists, render_default_config, render_nginx_config};
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::layout;
+use crate::mods::logger::{log_diff, log_error, log_info, log_step, log_success, log_warning};
+use crate::mods::marker::find_stale_sites;
+use crate::mods::models::{Config, ConfigFormat, DomainConfig};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
+use crate::mods::ssl::certificate_exists;
+use crate::mods::template::load_template;
+use crate::mods::utils::unified_diff;
+use crate::mods::validate::{canonical_names, check_domains, normalize_domain};
+
+/// Exit code of `apply --plan` when the plan contains changes
+pub const EXIT_PLAN_CHANGES: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Create,
+    Update,
+    Remove,
+    Unchanged,
+}
+
+/// Planned change of a single file managed by apply
+#[derive(Debug, Serialize)]
+pub struct FileChange {
+    /// Domain name, "default" or "nginx.conf"
+    pub name: String,
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Whether the site must be (re-)linked into sites-enabled
+    pub enable: bool,
+    pub reason: Option<String>,
+    #[serde(skip)]
+    pub current: Option<String>,
+    #[serde(skip)]
+    pub desired: Option<String>,
+}
+
+/// Domain of the config that apply would reject without writing anything
+#[derive(Debug, Serialize)]
+pub struct RejectedDomain {
+    /// Name as written in the config
+    pub domain: String,
+    pub error: Error,
+    /// Whether the current configuration of the domain stays live
+    pub kept: bool,
+}
+
+/// Everything `apply` would do, computed without touching the system
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub changes: Vec<FileChange>,
+    /// Domains for which a Let's Encrypt certificate would be requested
+    pub certificates: Vec<String>,
+    /// Domains failing validation, skipped by apply like the others are applied
+    pub rejected: Vec<RejectedDomain>,
+    pub warnings: Vec<String>,
+}
+
+impl CommandReport for Plan {}
+
+impl Plan {
+    pub fn has_changes(&self) -> bool {
+        !self.certificates.is_empty()
+            || self.changes.iter().any(|c| c.kind != ChangeKind::Unchanged || c.enable)
+    }
+
+    fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|c| c.kind == kind).count()
+    }
+}
+
+/// Compute the plan of a parsed config against the live nginx directories.
+/// Each domain is planned on its own, as apply does: an invalid one is
+/// reported as rejected and the others are still planned.
+pub fn build_plan(config: &Config, prune: bool) -> Result<Plan> {
+    let checked_domains = check_domains(config);
+    let config = &canonical_names(config);
+    let mut plan = Plan::default();
+
+    // Broken configurations are removed at the start of apply
+    let broken_configs = detect_broken_configs().unwrap_or_else(|e| {
+        plan.warnings.push(format!("Could not check for broken configurations: {}", e));
+        vec![]
+    });
+    for broken in broken_configs {
+        let path = layout().site_path(&broken);
+        plan.changes.push(FileChange {
+            current: fs::read_to_string(&path).ok(),
+            name: broken,
+            path,
+            kind: ChangeKind::Remove,
+            enable: false,
+            reason: Some("broken configuration".to_string()),
+            desired: None,
+        });
+    }
+
+    // The main nginx.conf and the default site are rewritten on every apply
+    plan.changes.push(plan_file(
+        "nginx.conf",
+        &paths().nginx_conf,
+        load_template("nginx_main.conf")?,
+        false,
+    ));
+    plan.changes.push(plan_file(
+        "default",
+        &layout().site_path("default"),
+        render_default_config()?,
+        false,
+    ));
+
+    for (domain_config, checked) in config.domains.iter().zip(checked_domains) {
+        let domain_config = &match checked {
+            Ok(checked) => checked,
+            Err(error) => {
+                plan.rejected.push(RejectedDomain {
+                    kept: normalize_domain(&domain_config.domain).is_ok() && config_exists(&domain_config.domain),
+                    domain: domain_config.domain.clone(),
+                    error,
+                });
+                continue;
+            }
+        };
+        let desired_config = desired_domain_config(domain_config, &mut plan);
+        let rendered = render_nginx_config(&desired_config)?;
+        let path = layout().site_path(&domain_config.domain);
+        let enabled = layout().is_enabled(&domain_config.domain);
+
+        plan.changes.push(plan_file(&domain_config.domain, &path, rendered, !enabled));
+    }
+
+    if prune {
+        for site in find_stale_sites(config)? {
+            if plan.changes.iter().any(|c| c.name == site) {
+                continue;
+            }
+            let path = layout().site_path(&site);
+            plan.changes.push(FileChange {
+                current: fs::read_to_string(&path).ok(),
+                name: site,
+                path,
+                kind: ChangeKind::Remove,
+                enable: false,
+                reason: Some("no longer in config".to_string()),
+                desired: None,
+            });
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Config that apply would end up writing for a domain, recording certificate requests
+fn desired_domain_config(domain_config: &DomainConfig, plan: &mut Plan) -> DomainConfig {
+    let mut desired = domain_config.clone();
+    if !domain_config.ssl {
+        return desired;
+    }
+
+    if domain_config.domain.parse::<std::net::IpAddr>().is_ok() {
+        plan.warnings.push(format!(
+            "{}: SSL is not supported for IP addresses, HTTP will be used",
+            domain_config.domain
+        ));
+        desired.ssl = false;
+        return desired;
+    }
+
+    if !certificate_exists(&domain_config.domain) {
+        plan.certificates.push(domain_config.domain.clone());
+    }
+    desired
+}
+
+fn plan_file(name: &str, path: &str, desired: String, enable: bool) -> FileChange {
+    let current = fs::read_to_string(path).ok();
+    let kind = match &current {
+        None => ChangeKind::Create,
+        Some(content) if *content == desired => ChangeKind::Unchanged,
+        Some(_) => ChangeKind::Update,
+    };
+
+    FileChange {
+        name: name.to_string(),
+        path: path.to_string(),
+        kind,
+        enable,
+        reason: None,
+        current,
+        desired: Some(desired),
+    }
+}
+
+/// Print the plan of a config file
+pub fn plan_config(config_path: &str, format: Option<ConfigFormat>, prune: bool) -> Result<Plan> {
+    log_step("> Planning configuration (no changes will be made)...");
+
+    let config = read_config(config_path, format)?;
+    let plan = build_plan(&config, prune)?;
+
+    log_step("\n> Files:");
+    for change in &plan.changes {
+        let symbol = match change.kind {
+            ChangeKind::Create => "+",
+            ChangeKind::Update => "~",
+            ChangeKind::Remove => "-",
+            ChangeKind::Unchanged => "=",
+        };
+        let mut line = format!("   {} {} ({})", symbol, change.name, change.path);
+        if let Some(reason) = &change.reason {
+            line.push_str(&format!(" - {}", reason));
+        }
+        if change.enable {
+            line.push_str(" [will be enabled]");
+        }
+        log_info(&line);
+    }
+
+    for change in &plan.changes {
+        if change.kind == ChangeKind::Unchanged {
+            continue;
+        }
+        let current = change.current.as_deref().unwrap_or("");
+        let desired = change.desired.as_deref().unwrap_or("");
+        log_info("");
+        log_diff(&unified_diff(current, desired, &change.path, &format!("{} (planned)", change.path)));
+    }
+
+    if !plan.rejected.is_empty() {
+        log_step("\n> Rejected domains:");
+        for rejected in &plan.rejected {
+            log_error(&format!("   ✗ {}: {}", rejected.domain, rejected.error));
+            if rejected.kept {
+                log_warning("     ⚠️  Its current configuration would be kept");
+            }
+        }
+    }
+
+    if !plan.certificates.is_empty() {
+        log_step("\n> Certificates to request:");
+        for domain in &plan.certificates {
+            log_info(&format!("   - {}", domain));
+        }
+    }
+
+    for warning in &plan.warnings {
+        log_warning(&format!("⚠️  {}", warning));
+    }
+
+    log_info(&format!(
+        "\nPlan: {} to create, {} to update, {} to remove, {} certificate(s) to request, {} domain(s) rejected",
+        plan.count(ChangeKind::Create),
+        plan.count(ChangeKind::Update),
+        plan.count(ChangeKind::Remove),
+        plan.certificates.len(),
+        plan.rejected.len()
+    ));
+
+    if plan.has_changes() {
+        log_warning("⚠️  Changes pending");
+    } else {
+        log_success("✓ No changes, the system matches the configuration");
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    fn config(value: serde_json::Value) -> Config {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Write the files of a plan as apply would
+    fn write_plan(plan: &Plan) {
+        for change in &plan.changes {
+            if let Some(desired) = &change.desired {
+                fs::write(&change.path, desired).unwrap();
+            }
+            if change.enable {
+                layout().enable_site(&change.name).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn plans_valid_domains_and_reports_rejected_ones() {
+        let _sandbox = sandbox();
+        let config = config(serde_json::json!({ "domains": [
+            { "domain": "Planned.Example.com", "port": 3000 },
+            { "domain": "bad domain", "port": 3001 },
+            { "domain": "bad-size.example.com", "port": 3002, "max_body_size": "lots" }
+        ]}));
+
+        let plan = build_plan(&config, false).unwrap();
+        let rejected: Vec<(&str, bool)> = plan.rejected.iter().map(|r| (r.domain.as_str(), r.kept)).collect();
+        assert_eq!(rejected, [("bad domain", false), ("bad-size.example.com", false)]);
+
+        let change = plan.changes.iter().find(|c| c.name == "planned.example.com").unwrap();
+        assert_eq!(change.kind, ChangeKind::Create);
+        assert!(change.enable);
+        assert!(plan.has_changes());
+    }
+
+    #[test]
+    fn plan_is_empty_once_applied_and_keeps_rejected_sites() {
+        let _sandbox = sandbox();
+        let nginx_conf = fs::read_to_string(&paths().nginx_conf).unwrap();
+        let applied = config(serde_json::json!({ "domains": [{ "domain": "applied.example.com", "port": 3000 }] }));
+
+        write_plan(&build_plan(&applied, false).unwrap());
+        let plan = build_plan(&applied, false).unwrap();
+        assert!(!plan.has_changes(), "{:?}", plan.changes);
+
+        // An invalid update is rejected, the live site stays as it is
+        let invalid = config(serde_json::json!({
+            "domains": [{ "domain": "applied.example.com", "port": 3000, "max_body_size": "lots" }]
+        }));
+        let plan = build_plan(&invalid, true).unwrap();
+        assert!(plan.rejected[0].kept);
+        assert!(!plan.changes.iter().any(|c| c.name == "applied.example.com"));
+        assert!(!plan.has_changes());
+
+        fs::write(&paths().nginx_conf, nginx_conf).unwrap();
+        for site in ["applied.example.com", "default"] {
+            for path in layout().site_files(site) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::mods::backup::{backup_sources, copy_entry, create_backup, open_backup, OpenedBackup};
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::layout;
+use crate::mods::logger::{log_error, log_info, log_step, log_success, log_warning};
+use crate::mods::nginx::{reload_nginx, test_nginx};
+use crate::mods::output::CommandReport;
+use crate::mods::paths::paths;
+use crate::mods::stage::Stage;
+use crate::mods::validate::normalize_domain;
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    pub backup: String,
+    pub path: String,
+    /// Set when a single site was restored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Backup of the configuration replaced by the restore
+    pub snapshot: String,
+    pub reloaded: bool,
+}
+
+impl CommandReport for RestoreReport {}
+
+/// Restore the saved files from a backup ("latest" for the most recent one).
+///
+/// The current configuration is backed up first, then the restored tree is
+/// staged and validated with `nginx -t` before being swapped in file by file,
+/// so nginx never sees a half-restored configuration. If the live configuration
+/// fails its test after the swap, or nginx fails to reload with `reload`, the
+/// previous configuration is put back.
+pub fn restore_backup(backup_id: &str, reload: bool) -> Result<RestoreReport> {
+    let backup = open_backup(backup_id)?;
+    let live_layout = layout();
+
+    // Its sites would be written to directories nginx does not read
+    if backup.layout != live_layout {
+        return Err(Error::BackupIncompatible(format!(
+            "Backup {} uses the {} layout but nginx now uses {}, its sites can't be restored",
+            backup.id,
+            backup.layout.name(),
+            live_layout.name()
+        )));
+    }
+
+    log_step(&format!("🔄 Restoring from backup: {}", backup.location));
+    restore(&backup, None, reload)
+}
+
+/// Restore a single site from a backup, with its enabled state; the other
+/// sites are left untouched. Same safeguards as `restore_backup`.
+pub fn restore_site(backup_id: &str, domain: &str, reload: bool) -> Result<RestoreReport> {
+    // The default site is not a domain, but it is restored like the others
+    let domain = match domain {
+        "default" => domain.to_string(),
+        _ => normalize_domain(domain)?,
+    };
+    let backup = open_backup(backup_id)?;
+    let live_layout = layout();
+
+    if backup.layout != live_layout {
+        return Err(Error::BackupIncompatible(format!(
+            "Backup {} uses the {} layout but nginx now uses {}, restore it as a whole instead",
+            backup.id,
+            backup.layout.name(),
+            live_layout.name()
+        )));
+    }
+
+    let is_saved = live_layout
+        .site_entries(&domain)
+        .iter()
+        .any(|(dir, file)| backup.path.join(dir).join(file).symlink_metadata().is_ok());
+    if !is_saved {
+        return Err(Error::BackupMissing(format!("Site {} is not in backup {}", domain, backup.id)));
+    }
+
+    log_step(&format!("🔄 Restoring {} from backup: {}", domain, backup.location));
+    restore(&backup, Some(&domain), reload)
+}
+
+fn restore(backup: &OpenedBackup, domain: Option<&str>, reload: bool) -> Result<RestoreReport> {
+    // Back up the current state, to be able to return to it
+    log_step("\n> Creating backup of the current configuration...");
+    let snapshot = create_backup("restore")?;
+
+    // Stage the restored configuration in a copy, nothing live is modified
+    log_step("\n> Staging restored configuration...");
+    let mut stage = Stage::new()?;
+    stage_backup(&mut stage, &backup.path, domain)?;
+
+    // Test the copy
+    log_step("\n> Testing restored nginx configuration...");
+    if let Err(e) = stage.validate() {
+        log_error("❌ Configuration test failed!");
+        log_error(&format!("   {}", e));
+        return Err(Error::NginxTest(
+            "The restored configuration is invalid. The live configuration was not modified.".to_string(),
+        ));
+    }
+    log_success("✓ Restored configuration is valid");
+
+    // Swap the validated copy into place, then test and reload
+    log_step("\n> Swapping restored files into place...");
+    let swapped = stage
+        .commit()
+        .and_then(|_| if domain.is_none() { restore_web_pages(&backup.path) } else { Ok(()) })
+        .and_then(|_| test_nginx())
+        .and_then(|_| if reload { reload_nginx() } else { Ok(()) });
+
+    // On failure, return to the saved state
+    if let Err(e) = swapped {
+        log_error(&format!("❌ Restore failed: {}", e));
+        log_step(&format!("\n> Reverting to {}...", snapshot));
+        return match revert(&snapshot) {
+            Ok(()) => {
+                log_success("✓ Previous configuration put back");
+                // The files are back; nginx may still run the restored config
+                if reload && let Err(reload_error) = reload_nginx() {
+                    log_warning(&format!("⚠️  Nginx could not be reloaded: {}", reload_error));
+                }
+                Err(e.map_message(|m| format!("{} (reverted to {})", m, snapshot)))
+            }
+            Err(revert_error) => Err(e.map_message(|m| {
+                format!(
+                    "{}; reverting to {} failed too: {}. Run `xynginc restore {}`",
+                    m, snapshot, revert_error, snapshot
+                )
+            })),
+        };
+    }
+
+    match domain {
+        Some(domain) => log_success(&format!("✓ {} restored successfully", domain)),
+        None => log_success("✓ Backup restored successfully"),
+    }
+    if !reload {
+        log_info("   Run `xynginc reload` to apply it");
+    }
+
+    Ok(RestoreReport {
+        backup: backup.id.clone(),
+        path: backup.location.clone(),
+        domain: domain.map(str::to_string),
+        snapshot,
+        reloaded: reload,
+    })
+}
+
+/// Put back the configuration saved before a restore, without validation: it
+/// is what nginx was running
+fn revert(snapshot: &str) -> Result<()> {
+    let snapshot = open_backup(snapshot)?;
+    let mut stage = Stage::new()?;
+    stage_backup(&mut stage, &snapshot.path, None)?;
+    stage.commit()?;
+    restore_web_pages(&snapshot.path)
+}
+
+/// Stage the nginx files of a backup, or only those of one site
+fn stage_backup(stage: &mut Stage, backup_path: &Path, domain: Option<&str>) -> Result<()> {
+    if let Some(domain) = domain {
+        let layout = layout();
+        stage.remove_site(domain)?;
+        for ((dir, file), live) in layout.site_entries(domain).into_iter().zip(layout.site_files(domain)) {
+            let saved = backup_path.join(dir).join(file);
+            if saved.symlink_metadata().is_ok() {
+                stage.restore_entry(&saved, &live, false)?;
+            }
+        }
+        return Ok(());
+    }
+
+    for source in backup_sources() {
+        let saved = backup_path.join(source.name);
+        if is_nginx_file(&source.path) && saved.symlink_metadata().is_ok() {
+            stage.restore_entry(&saved, &source.path, source.replace_on_restore)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy back the saved entries nginx does not read (the pages of the web root)
+fn restore_web_pages(backup_path: &Path) -> Result<()> {
+    for source in backup_sources() {
+        let saved = backup_path.join(source.name);
+        if is_nginx_file(&source.path) || saved.symlink_metadata().is_err() {
+            continue;
+        }
+
+        let live = Path::new(&source.path);
+        if let Some(parent) = live.parent() {
+            fs::create_dir_all(parent).map_err(Error::io(&format!("Failed to create {}", parent.display())))?;
+        }
+        copy_entry(&saved, live)?;
+    }
+    Ok(())
+}
+
+fn is_nginx_file(path: &str) -> bool {
+    Path::new(path).starts_with(&paths().nginx_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+    use std::os::unix::fs::symlink;
+
+    fn available(name: &str) -> String {
+        format!("{}/{}", paths().sites_available, name)
+    }
+
+    fn enabled(name: &str) -> String {
+        format!("{}/{}", paths().sites_enabled, name)
+    }
+
+    /// Write the file of an enabled site
+    fn write_site(name: &str, content: &str) {
+        fs::write(available(name), content).unwrap();
+        if fs::symlink_metadata(enabled(name)).is_err() {
+            symlink(format!("../sites-available/{}", name), enabled(name)).unwrap();
+        }
+    }
+
+    fn read(path: &str) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn cleanup(names: &[&str]) {
+        for name in names {
+            let _ = fs::remove_file(enabled(name));
+            let _ = fs::remove_file(available(name));
+        }
+        let _ = fs::remove_dir_all(&paths().web_root);
+        let _ = fs::remove_dir_all(&paths().backup_dir);
+    }
+
+    #[test]
+    fn reverts_to_the_configuration_saved_before_the_restore() {
+        let _sandbox = sandbox();
+        let index = format!("{}/index.html", paths().web_root);
+        let nginx_conf = read(&paths().nginx_conf);
+        write_site("a.com", "old");
+        fs::create_dir_all(&paths().web_root).unwrap();
+        fs::write(&index, "old").unwrap();
+        let snapshot = create_backup("test").unwrap();
+
+        write_site("a.com", "new");
+        write_site("b.com", "new");
+        fs::write(&index, "new").unwrap();
+        fs::write(&paths().nginx_conf, "broken").unwrap();
+        revert(&snapshot).unwrap();
+
+        assert_eq!(read(&available("a.com")), "old");
+        assert_eq!(read(&enabled("a.com")), "old");
+        assert_eq!(read(&index), "old");
+        assert_eq!(read(&paths().nginx_conf), nginx_conf);
+        // sites-enabled is replaced, sites-available merged
+        assert!(fs::symlink_metadata(enabled("b.com")).is_err());
+        assert!(Path::new(&available("b.com")).is_file());
+        cleanup(&["a.com", "b.com"]);
+    }
+
+    #[test]
+    fn stages_a_single_site_of_a_backup() {
+        let _sandbox = sandbox();
+        write_site("a.com", "old");
+        write_site("b.com", "old");
+        let backup = open_backup(&create_backup("test").unwrap()).unwrap();
+
+        write_site("b.com", "new");
+        fs::remove_file(enabled("a.com")).unwrap();
+        fs::write(available("a.com"), "new").unwrap();
+
+        let mut stage = Stage::new().unwrap();
+        stage_backup(&mut stage, &backup.path, Some("a.com")).unwrap();
+        stage.commit().unwrap();
+
+        assert_eq!(read(&enabled("a.com")), "old");
+        assert_eq!(read(&available("b.com")), "new");
+        drop(backup);
+        cleanup(&["a.com", "b.com"]);
+    }
+}
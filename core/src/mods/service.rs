@@ -0,0 +1,355 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::log_info;
+use crate::mods::nginx::live_config;
+use crate::mods::paths::paths;
+
+static SERVICE_KIND: OnceLock<ServiceKind> = OnceLock::new();
+
+/// How long the signal backend waits for the new generation of workers
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Controls the nginx service: reload, start, start at boot and status
+pub trait ServiceManager {
+    fn kind(&self) -> ServiceKind;
+
+    /// Make nginx re-read its configuration without dropping connections
+    fn reload(&self) -> Result<()>;
+
+    fn start(&self) -> Result<()>;
+
+    /// Start nginx at boot
+    fn enable(&self) -> Result<()>;
+
+    fn is_active(&self) -> Result<bool>;
+}
+
+/// Available service manager backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceKind {
+    /// `systemctl`
+    Systemd,
+    /// `rc-service` (Alpine, Gentoo)
+    OpenRc,
+    /// `service` and the /etc/init.d scripts
+    SysV,
+    /// No init system (containers, runit): signals sent to the PID of the nginx master
+    Signal,
+}
+
+impl ServiceKind {
+    /// Parse a backend name as written in the settings or the environment
+    pub fn parse(value: &str) -> Option<ServiceKind> {
+        match value {
+            "systemd" | "systemctl" => Some(ServiceKind::Systemd),
+            "openrc" => Some(ServiceKind::OpenRc),
+            "sysv" | "service" => Some(ServiceKind::SysV),
+            "signal" => Some(ServiceKind::Signal),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServiceKind::Systemd => "systemd",
+            ServiceKind::OpenRc => "openrc",
+            ServiceKind::SysV => "sysv",
+            ServiceKind::Signal => "signal",
+        }
+    }
+
+    /// Guess the init system nginx runs under
+    pub fn detect() -> ServiceKind {
+        ServiceKind::detect_with(|path| Path::new(path).exists(), command_exists)
+    }
+
+    /// `detect` with the checks of the filesystem and of the PATH given
+    fn detect_with(path_exists: impl Fn(&str) -> bool, command_exists: impl Fn(&str) -> bool) -> ServiceKind {
+        // Same test as sd_booted(): systemd is installed but may not be PID 1 in containers
+        if path_exists("/run/systemd/system") && command_exists("systemctl") {
+            ServiceKind::Systemd
+        } else if path_exists("/run/openrc") && command_exists("rc-service") {
+            ServiceKind::OpenRc
+        } else if path_exists("/etc/init.d/nginx") && command_exists("service") {
+            ServiceKind::SysV
+        } else {
+            ServiceKind::Signal
+        }
+    }
+}
+
+/// Service manager of the process: the one set in the settings, otherwise the detected one
+pub fn service_manager() -> &'static dyn ServiceManager {
+    let kind = *SERVICE_KIND.get_or_init(|| paths().service_manager.unwrap_or_else(ServiceKind::detect));
+    match kind {
+        ServiceKind::Systemd => &Systemd,
+        ServiceKind::OpenRc => &OpenRc,
+        ServiceKind::SysV => &SysV,
+        ServiceKind::Signal => &Signal,
+    }
+}
+
+pub struct Systemd;
+
+impl ServiceManager for Systemd {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::Systemd
+    }
+
+    fn reload(&self) -> Result<()> {
+        run_checked("systemctl", &["reload", "nginx"], "reload nginx")
+    }
+
+    fn start(&self) -> Result<()> {
+        run_checked("systemctl", &["start", "nginx"], "start nginx")
+    }
+
+    fn enable(&self) -> Result<()> {
+        run_checked("systemctl", &["enable", "nginx"], "enable nginx service")
+    }
+
+    fn is_active(&self) -> Result<bool> {
+        Ok(run("systemctl", &["is-active", "nginx"], "check nginx status")?.status.success())
+    }
+}
+
+pub struct OpenRc;
+
+impl ServiceManager for OpenRc {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::OpenRc
+    }
+
+    fn reload(&self) -> Result<()> {
+        run_checked("rc-service", &["nginx", "reload"], "reload nginx")
+    }
+
+    fn start(&self) -> Result<()> {
+        run_checked("rc-service", &["nginx", "start"], "start nginx")
+    }
+
+    fn enable(&self) -> Result<()> {
+        run_checked("rc-update", &["add", "nginx", "default"], "enable nginx service")
+    }
+
+    fn is_active(&self) -> Result<bool> {
+        Ok(run("rc-service", &["nginx", "status"], "check nginx status")?.status.success())
+    }
+}
+
+pub struct SysV;
+
+impl ServiceManager for SysV {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::SysV
+    }
+
+    fn reload(&self) -> Result<()> {
+        run_checked("service", &["nginx", "reload"], "reload nginx")
+    }
+
+    fn start(&self) -> Result<()> {
+        run_checked("service", &["nginx", "start"], "start nginx")
+    }
+
+    fn enable(&self) -> Result<()> {
+        if command_exists("update-rc.d") {
+            run_checked("update-rc.d", &["nginx", "defaults"], "enable nginx service")
+        } else if command_exists("chkconfig") {
+            run_checked("chkconfig", &["nginx", "on"], "enable nginx service")
+        } else {
+            log_info("   No update-rc.d or chkconfig found, nginx must be started at boot manually");
+            Ok(())
+        }
+    }
+
+    fn is_active(&self) -> Result<bool> {
+        Ok(run("service", &["nginx", "status"], "check nginx status")?.status.success())
+    }
+}
+
+/// Talks to the nginx master process directly, for hosts without a usable init system
+pub struct Signal;
+
+impl Signal {
+    /// PID of the nginx master, read from the PID file
+    fn master_pid(&self) -> Result<libc::pid_t> {
+        let pid_file = &paths().pid_file;
+        let content = fs::read_to_string(pid_file)
+            .map_err(|e| Error::NginxService(format!("Failed to read nginx PID file {}: {}", pid_file, e)))?;
+        content
+            .trim()
+            .parse()
+            .map_err(|_| Error::NginxService(format!("Invalid nginx PID file {}: '{}'", pid_file, content.trim())))
+    }
+}
+
+impl ServiceManager for Signal {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::Signal
+    }
+
+    /// Send SIGHUP to the master and wait for it to replace its workers.
+    /// nginx keeps the old workers when the new configuration is invalid,
+    /// so a new generation is the only proof the reload took effect.
+    fn reload(&self) -> Result<()> {
+        let pid = self.master_pid()?;
+        let old_workers = worker_pids(pid);
+
+        // SAFETY: kill() has no memory safety requirements
+        if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(Error::NginxService(format!("Failed to send SIGHUP to nginx (PID {}): {}", pid, e)));
+        }
+
+        if old_workers.is_empty() {
+            // Workers can't be listed without /proc: trust the signal
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        while started.elapsed() < RELOAD_TIMEOUT {
+            thread::sleep(Duration::from_millis(100));
+            if worker_pids(pid).iter().any(|worker| !old_workers.contains(worker)) {
+                return Ok(());
+            }
+        }
+
+        Err(Error::NginxService(format!(
+            "nginx (PID {}) did not start new workers after SIGHUP, see its error log",
+            pid
+        )))
+    }
+
+    fn start(&self) -> Result<()> {
+        let mut args = vec![];
+        if let Some(conf) = live_config() {
+            args.extend(["-c", conf]);
+        }
+        run_checked("nginx", &args, "start nginx")
+    }
+
+    fn enable(&self) -> Result<()> {
+        log_info("   No service manager in use, nginx must be started at boot by the host");
+        Ok(())
+    }
+
+    fn is_active(&self) -> Result<bool> {
+        let Ok(pid) = self.master_pid() else {
+            return Ok(false);
+        };
+        // SAFETY: signal 0 only checks that the process exists
+        let alive = unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+        Ok(alive)
+    }
+}
+
+/// Children of the nginx master, read from /proc
+fn worker_pids(master: libc::pid_t) -> HashSet<libc::pid_t> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return HashSet::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<libc::pid_t>().ok())
+        .filter(|pid| {
+            // The parent PID is the 2nd field after the command name, which may contain spaces
+            fs::read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| {
+                    let fields = &stat[stat.rfind(')')? + 1..];
+                    fields.split_whitespace().nth(1)?.parse::<libc::pid_t>().ok()
+                })
+                == Some(master)
+        })
+        .collect()
+}
+
+fn command_exists(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+fn run(program: &str, args: &[&str], action: &str) -> Result<Output> {
+    Command::new(program)
+        .args(args)
+        .output()
+        .map_err(Error::io(&format!("Failed to {}", action)))
+}
+
+/// Run a command, failing with its stderr when it exits with an error
+fn run_checked(program: &str, args: &[&str], action: &str) -> Result<()> {
+    let output = run(program, args, action)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(Error::NginxService(format!("Failed to {}:\n{}", action, stderr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    fn detect(paths: &[&str], commands: &[&str]) -> ServiceKind {
+        ServiceKind::detect_with(|path| paths.contains(&path), |command| commands.contains(&command))
+    }
+
+    #[test]
+    fn detects_the_running_init_system() {
+        let all_commands = ["systemctl", "rc-service", "service"];
+        assert_eq!(detect(&["/run/systemd/system", "/etc/init.d/nginx"], &all_commands), ServiceKind::Systemd);
+        assert_eq!(detect(&["/run/openrc", "/etc/init.d/nginx"], &all_commands), ServiceKind::OpenRc);
+        assert_eq!(detect(&["/etc/init.d/nginx"], &all_commands), ServiceKind::SysV);
+        // systemctl installed in a container that doesn't run systemd
+        assert_eq!(detect(&[], &all_commands), ServiceKind::Signal);
+        assert_eq!(detect(&["/run/systemd/system", "/run/openrc", "/etc/init.d/nginx"], &[]), ServiceKind::Signal);
+    }
+
+    #[test]
+    fn parses_backend_names() {
+        for kind in [ServiceKind::Systemd, ServiceKind::OpenRc, ServiceKind::SysV, ServiceKind::Signal] {
+            assert_eq!(ServiceKind::parse(kind.name()), Some(kind));
+        }
+        assert_eq!(ServiceKind::parse("systemctl"), Some(ServiceKind::Systemd));
+        assert_eq!(ServiceKind::parse("service"), Some(ServiceKind::SysV));
+        assert_eq!(ServiceKind::parse("upstart"), None);
+    }
+
+    #[test]
+    fn settings_select_the_backend() {
+        let _sandbox = sandbox();
+        assert_eq!(service_manager().kind(), ServiceKind::Signal);
+    }
+
+    #[test]
+    fn signal_backend_reads_the_master_pid() {
+        let _sandbox = sandbox();
+        let pid_file = &paths().pid_file;
+        fs::create_dir_all(Path::new(pid_file).parent().unwrap()).unwrap();
+
+        let _ = fs::remove_file(pid_file);
+        assert!(!Signal.is_active().unwrap());
+
+        fs::write(pid_file, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(Signal.master_pid().unwrap(), std::process::id() as libc::pid_t);
+        assert!(Signal.is_active().unwrap());
+
+        fs::write(pid_file, "nginx\n").unwrap();
+        assert!(matches!(Signal.master_pid(), Err(Error::NginxService(_))));
+        fs::remove_file(pid_file).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::mods::backup::open_backup;
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::{layout, Layout};
+use crate::mods::logger::{log_diff, log_info, log_step, log_success};
+use crate::mods::output::CommandReport;
+use crate::mods::plan::ChangeKind;
+use crate::mods::utils::unified_diff;
+
+/// Side of a diff standing for the live configuration
+pub const LIVE: &str = "live";
+
+/// A site as found in the live directories or in a backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteState {
+    /// `None` when the site is only a link to a file outside the site directories
+    pub content: Option<String>,
+    pub enabled: bool,
+}
+
+/// Difference of a site between two snapshots
+#[derive(Debug, Serialize)]
+pub struct SiteDiff {
+    pub name: String,
+    /// Create: only in the second snapshot, Remove: only in the first,
+    /// Unchanged: same content but a different enabled state
+    pub kind: ChangeKind,
+    /// Enabled state in the first snapshot, `None` when the site is absent
+    pub enabled_before: Option<bool>,
+    pub enabled_after: Option<bool>,
+    /// Unified diff of the site file, for a site changed on both sides
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// Sites that differ between a backup and another backup or the live configuration
+#[derive(Debug, Serialize)]
+pub struct BackupDiff {
+    pub from: String,
+    pub to: String,
+    pub sites: Vec<SiteDiff>,
+}
+
+impl CommandReport for BackupDiff {}
+
+/// Sites of a tree of site directories, by name. `dir` gives the path of a
+/// site directory from its name ("sites-available", "sites-enabled", "conf.d").
+pub fn read_sites(layout: Layout, dir: impl Fn(&str) -> PathBuf) -> Result<BTreeMap<String, SiteState>> {
+    let mut sites = BTreeMap::new();
+
+    match layout {
+        Layout::SitesEnabled => {
+            let available = dir("sites-available");
+            let enabled = dir("sites-enabled");
+            for name in file_names(&available)? {
+                let content = fs::read_to_string(available.join(&name)).ok();
+                let is_enabled = enabled.join(&name).symlink_metadata().is_ok();
+                sites.insert(name, SiteState { content, enabled: is_enabled });
+            }
+            // Sites enabled without a file in sites-available
+            for name in file_names(&enabled)? {
+                if sites.contains_key(&name) {
+                    continue;
+                }
+                let path = enabled.join(&name);
+                let content = match path.is_symlink() {
+                    // The target may point anywhere, e.g. to the live tree from a backup
+                    true => None,
+                    false => fs::read_to_string(&path).ok(),
+                };
+                sites.insert(name, SiteState { content, enabled: true });
+            }
+        }
+        Layout::ConfD => {
+            let conf_d = dir("conf.d");
+            for file_name in file_names(&conf_d)? {
+                let (name, enabled) = match file_name.strip_suffix(".conf.disabled") {
+                    Some(name) => (name, false),
+                    None => match file_name.strip_suffix(".conf") {
+                        Some(name) => (name, true),
+                        None => continue,
+                    },
+                };
+                // With both files present, the enabled one is what nginx reads
+                if !enabled && sites.contains_key(name) {
+                    continue;
+                }
+                let content = fs::read_to_string(conf_d.join(&file_name)).ok();
+                sites.insert(name.to_string(), SiteState { content, enabled });
+            }
+        }
+    }
+
+    Ok(sites)
+}
+
+/// Sites of the live configuration
+pub fn live_sites() -> Result<BTreeMap<String, SiteState>> {
+    let dirs = layout().site_dirs();
+    read_sites(layout(), |name| {
+        dirs.iter()
+            .find(|dir| dir.name == name)
+            .map(|dir| PathBuf::from(&dir.path))
+            .unwrap_or_default()
+    })
+}
+
+fn file_names(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(Error::io(&format!("Failed to read {}", dir.display())))? {
+        let entry = entry.map_err(Error::io("Failed to read entry"))?;
+        if !entry.path().is_dir() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Compare two sets of sites; only the sites that differ are returned
+pub fn diff_sites(
+    before: &BTreeMap<String, SiteState>,
+    after: &BTreeMap<String, SiteState>,
+    before_label: &str,
+    after_label: &str,
+) -> Vec<SiteDiff> {
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name);
+            let new = after.get(name);
+            if old == new {
+                return None;
+            }
+
+            let old_content = old.and_then(|site| site.content.as_deref());
+            let new_content = new.and_then(|site| site.content.as_deref());
+            let kind = match (old, new) {
+                (None, _) => ChangeKind::Create,
+                (_, None) => ChangeKind::Remove,
+                _ if old_content != new_content => ChangeKind::Update,
+                _ => ChangeKind::Unchanged,
+            };
+            let diff = (kind == ChangeKind::Update).then(|| {
+                unified_diff(
+                    old_content.unwrap_or_default(),
+                    new_content.unwrap_or_default(),
+                    &format!("{}/{}", before_label, name),
+                    &format!("{}/{}", after_label, name),
+                )
+            });
+
+            Some(SiteDiff {
+                name: name.clone(),
+                kind,
+                enabled_before: old.map(|site| site.enabled),
+                enabled_after: new.map(|site| site.enabled),
+                diff,
+            })
+        })
+        .collect()
+}
+
+/// Show how the sites changed from a backup to another backup or to the live
+/// configuration (`live`)
+pub fn diff_backup(backup_id: &str, other: &str) -> Result<BackupDiff> {
+    let backup = open_backup(backup_id)?;
+    let before = read_sites(backup.layout, |name| backup.path.join(name))?;
+
+    let (to, after) = match other {
+        LIVE => (LIVE.to_string(), live_sites()?),
+        other_id => {
+            let other = open_backup(other_id)?;
+            let sites = read_sites(other.layout, |name| other.path.join(name))?;
+            (other.id.clone(), sites)
+        }
+    };
+
+    log_step(&format!("Comparing {} → {}\n", backup.id, to));
+    let sites = diff_sites(&before, &after, &backup.id, &to);
+
+    let state = |enabled: Option<bool>| match enabled {
+        Some(true) => "enabled",
+        Some(false) => "disabled",
+        None => "absent",
+    };
+    for site in &sites {
+        let (sign, detail) = match site.kind {
+            ChangeKind::Create => ("+", format!("added, {}", state(site.enabled_after))),
+            ChangeKind::Remove => ("-", format!("removed, was {}", state(site.enabled_before))),
+            ChangeKind::Update | ChangeKind::Unchanged => {
+                let mut details = vec![];
+                if site.kind == ChangeKind::Update {
+                    details.push("changed".to_string());
+                }
+                if site.enabled_before != site.enabled_after {
+                    details.push(format!("{} → {}", state(site.enabled_before), state(site.enabled_after)));
+                }
+                ("~", details.join(", "))
+            }
+        };
+        log_info(&format!("   {} {} ({})", sign, site.name, detail));
+    }
+
+    for diff in sites.iter().filter_map(|site| site.diff.as_deref()) {
+        log_info("");
+        log_diff(diff);
+    }
+
+    if sites.is_empty() {
+        log_success("✓ No differences");
+    } else {
+        let count = |kind: ChangeKind| sites.iter().filter(|site| site.kind == kind).count();
+        let toggled = sites
+            .iter()
+            .filter(|site| site.enabled_before.is_some() && site.enabled_after.is_some())
+            .filter(|site| site.enabled_before != site.enabled_after)
+            .count();
+        log_info(&format!(
+            "\nDiff: {} added, {} changed, {} removed, {} enabled-state change(s)",
+            count(ChangeKind::Create),
+            count(ChangeKind::Update),
+            count(ChangeKind::Remove),
+            toggled
+        ));
+    }
+
+    Ok(BackupDiff {
+        from: backup.id.clone(),
+        to,
+        sites,
+    })
+}
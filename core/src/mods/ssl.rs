@@ -1,12 +1,15 @@
+use std::path::Path;
 use std::process::{Command, Stdio};
 
-use crate::mods::logger::{log_step, log_success, log_warning};
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::{log_stdio, log_step, log_success, log_warning};
 use crate::mods::models::DomainConfig;
+use crate::mods::paths::paths;
 
 /// Check if certbot nginx plugin is available
 fn check_certbot_nginx_plugin() -> bool {
     if let Ok(output) = Command::new("certbot")
-        .args(&["plugins", "--text"])
+        .args(["plugins", "--text"])
         .output()
     {
         let plugins_text = String::from_utf8_lossy(&output.stdout);
@@ -17,29 +20,58 @@ fn check_certbot_nginx_plugin() -> bool {
 }
 
 /// Install certbot nginx plugin
-fn install_certbot_nginx_plugin() -> Result<(), String> {
+fn install_certbot_nginx_plugin() -> Result<()> {
     log_warning("⚠️  Certbot nginx plugin not found. Installing...");
     
     let output = Command::new("apt-get")
-        .args(&["install", "-y", "python3-certbot-nginx"])
+        .args(["install", "-y", "python3-certbot-nginx"])
         .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
+        .stdout(log_stdio())
         .stderr(Stdio::inherit())
         .output()
-        .map_err(|e| format!("Failed to install certbot nginx plugin: {}", e))?;
+        .map_err(Error::io("Failed to install certbot nginx plugin"))?;
     
     if !output.status.success() {
-        return Err("Failed to install python3-certbot-nginx package".to_string());
+        return Err(Error::Command("Failed to install python3-certbot-nginx package".to_string()));
     }
     
     log_success("✓ Certbot nginx plugin installed");
     Ok(())
 }
 
-pub fn setup_ssl(config: &DomainConfig) -> Result<(), String> {
+/// Whether a Let's Encrypt certificate is already present for a domain
+pub fn certificate_exists(domain: &str) -> bool {
+    Path::new(&format!("{}/{}/fullchain.pem", paths().letsencrypt_live_dir, domain)).exists()
+}
+
+/// Expiry date (RFC 3339) of the Let's Encrypt certificate of a domain, read with openssl
+pub fn certificate_expiry(domain: &str) -> Option<String> {
+    let cert_path = format!("{}/{}/cert.pem", paths().letsencrypt_live_dir, domain);
+    if !Path::new(&cert_path).exists() {
+        return None;
+    }
+
+    let output = Command::new("openssl")
+        .args(["x509", "-enddate", "-noout", "-in", &cert_path])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // notAfter=Jan 21 12:00:00 2026 GMT
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let date = stdout.trim().strip_prefix("notAfter=")?;
+    chrono::NaiveDateTime::parse_from_str(date, "%b %e %H:%M:%S %Y GMT")
+        .ok()
+        .map(|d| d.and_utc().to_rfc3339())
+}
+
+/// Obtain a Let's Encrypt certificate for a domain with certbot
+pub fn setup_ssl(config: &DomainConfig) -> Result<()> {
     log_step(&format!("> Setting up SSL for {}...", config.domain));
 
-    let email = config.email.as_ref().ok_or("Email required for SSL")?;
+    let email = config.email.as_ref().ok_or_else(|| Error::InvalidConfig("Email required for SSL".to_string()))?;
 
     // Check if nginx plugin is available, install if missing
     if !check_certbot_nginx_plugin() {
@@ -47,7 +79,7 @@ pub fn setup_ssl(config: &DomainConfig) -> Result<(), String> {
     }
 
     let output = Command::new("certbot")
-        .args(&[
+        .args([
             "certonly",
             "--nginx",
             "-d",
@@ -58,7 +90,7 @@ pub fn setup_ssl(config: &DomainConfig) -> Result<(), String> {
             "--non-interactive",
         ])
         .output()
-        .map_err(|e| format!("Failed to run certbot: {}", e))?;
+        .map_err(Error::io("Failed to run certbot"))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -72,7 +104,7 @@ pub fn setup_ssl(config: &DomainConfig) -> Result<(), String> {
             // Retry certbot
             log_step("> Retrying SSL certificate request...");
             let retry_output = Command::new("certbot")
-                .args(&[
+                .args([
                     "certonly",
                     "--nginx",
                     "-d",
@@ -83,14 +115,14 @@ pub fn setup_ssl(config: &DomainConfig) -> Result<(), String> {
                     "--non-interactive",
                 ])
                 .output()
-                .map_err(|e| format!("Failed to retry certbot: {}", e))?;
+                .map_err(Error::io("Failed to retry certbot"))?;
             
             if !retry_output.status.success() {
                 let retry_stderr = String::from_utf8_lossy(&retry_output.stderr);
-                return Err(format!("Certbot failed after plugin installation:\n{}", retry_stderr));
+                return Err(Error::Certbot(format!("Certbot failed after plugin installation:\n{}", retry_stderr)));
             }
         } else {
-            return Err(format!("Certbot failed:\n{}\n{}", stderr, stdout));
+            return Err(Error::Certbot(format!("Certbot failed:\n{}\n{}", stderr, stdout)));
         }
     }
 
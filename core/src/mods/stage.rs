@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::mods::backup::copy_entry;
+use crate::mods::error::{Error, Result};
+use crate::mods::layout::{layout, Layout};
+use crate::mods::nginx::test_nginx_config;
+use crate::mods::paths::paths;
+
+/// Stages created by this process, numbering their directories
+static STAGE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Change recorded against a live path, replayed by `commit`
+#[derive(Debug, Clone)]
+enum StagedChange {
+    Write(String),
+    Link(String),
+    /// Copy of a saved file (e.g. from a backup), keeping its mode and owner
+    Copy(PathBuf),
+    Remove,
+}
+
+/// Staged state of a site, taken by `Stage::snapshot_site`
+pub struct SiteSnapshot {
+    entries: Vec<(String, Option<StagedChange>, Option<StagedEntry>)>,
+}
+
+/// Entry of the staged tree, as found when a snapshot is taken
+enum StagedEntry {
+    File(Vec<u8>),
+    Link(PathBuf),
+}
+
+/// Shadow copy of the nginx tree in which changes are prepared and validated
+/// before being swapped into the live directories.
+///
+/// Absolute references to the nginx directory are rewritten in the copy so that
+/// `nginx -t -c` only sees staged files.
+pub struct Stage {
+    root: PathBuf,
+    changes: BTreeMap<String, StagedChange>,
+}
+
+impl Stage {
+    /// Copy the live nginx tree into a fresh staging directory, private to
+    /// this stage so that concurrent runs don't share it
+    pub fn new() -> Result<Self> {
+        let id = STAGE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let root = Path::new(&paths().staging_dir).join(format!("nginx-{}-{}", process::id(), id));
+        if root.exists() {
+            fs::remove_dir_all(&root)
+                .map_err(Error::io("Failed to clear staging directory"))?;
+        }
+        fs::create_dir_all(&root).map_err(Error::io("Failed to create staging directory"))?;
+
+        let stage = Stage {
+            root,
+            changes: BTreeMap::new(),
+        };
+        stage.copy_tree(Path::new(&paths().nginx_dir), &stage.root)?;
+        Ok(stage)
+    }
+
+    /// Whether changes have been staged since the copy
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    /// Stage the content of a live file (e.g. /etc/nginx/nginx.conf)
+    pub fn write_file(&mut self, live_path: &str, content: &str) -> Result<()> {
+        let staged = self.staged_path(live_path)?;
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent).map_err(Error::io("Failed to create staging directory"))?;
+        }
+        fs::write(&staged, self.rewrite(content))
+            .map_err(Error::io(&format!("Failed to stage {}", live_path)))?;
+
+        self.changes.insert(live_path.to_string(), StagedChange::Write(content.to_string()));
+        Ok(())
+    }
+
+    /// Stage the configuration file of a site
+    pub fn write_site(&mut self, name: &str, content: &str) -> Result<()> {
+        self.write_file(&layout().site_path(name), content)
+    }
+
+    /// Stage the activation of a site: its sites-enabled symlink, or the
+    /// removal of a `.conf.disabled` leftover with the conf.d layout
+    pub fn enable_site(&mut self, name: &str) -> Result<()> {
+        let layout = layout();
+        let live_switch = layout.switch_path(name);
+        let staged_switch = self.staged_path(&live_switch)?;
+        remove_entry(&staged_switch)?;
+
+        if layout == Layout::SitesEnabled {
+            let live_target = layout.site_path(name);
+            symlink(self.staged_path(&live_target)?, &staged_switch)
+                .map_err(Error::io(&format!("Failed to stage symlink of {}", name)))?;
+            self.changes.insert(live_switch, StagedChange::Link(live_target));
+        } else if Path::new(&live_switch).symlink_metadata().is_ok() {
+            self.changes.insert(live_switch, StagedChange::Remove);
+        }
+        Ok(())
+    }
+
+    /// Stage the removal of every file of a site
+    pub fn remove_site(&mut self, name: &str) -> Result<()> {
+        for live_path in layout().site_files(name) {
+            remove_entry(&self.staged_path(&live_path)?)?;
+            self.changes.insert(live_path, StagedChange::Remove);
+        }
+        Ok(())
+    }
+
+    /// Record the staged state of a site, to put it back with `revert_site`
+    /// if a later change of the site fails
+    pub fn snapshot_site(&self, name: &str) -> Result<SiteSnapshot> {
+        let mut entries = vec![];
+        for live_path in layout().site_files(name) {
+            let staged = self.staged_path(&live_path)?;
+            let entry = match staged.symlink_metadata() {
+                Err(_) => None,
+                Ok(metadata) if metadata.is_symlink() => Some(StagedEntry::Link(
+                    fs::read_link(&staged).map_err(Error::io(&format!("Failed to read {}", staged.display())))?,
+                )),
+                Ok(_) => Some(StagedEntry::File(
+                    fs::read(&staged).map_err(Error::io(&format!("Failed to read {}", staged.display())))?,
+                )),
+            };
+            let change = self.changes.get(&live_path).cloned();
+            entries.push((live_path, change, entry));
+        }
+        Ok(SiteSnapshot { entries })
+    }
+
+    /// Put a site back in the state recorded by `snapshot_site`, keeping the
+    /// changes staged before it (e.g. the removal of a broken site)
+    pub fn revert_site(&mut self, snapshot: SiteSnapshot) -> Result<()> {
+        for (live_path, change, entry) in snapshot.entries {
+            let staged = self.staged_path(&live_path)?;
+            remove_entry(&staged)?;
+            match entry {
+                Some(StagedEntry::Link(target)) => symlink(&target, &staged)
+                    .map_err(Error::io(&format!("Failed to restore {}", staged.display())))?,
+                Some(StagedEntry::File(bytes)) => fs::write(&staged, bytes)
+                    .map_err(Error::io(&format!("Failed to restore {}", staged.display())))?,
+                None => {}
+            }
+
+            match change {
+                Some(change) => self.changes.insert(live_path, change),
+                None => self.changes.remove(&live_path),
+            };
+        }
+        Ok(())
+    }
+
+    /// Stage a saved file, symlink or directory (e.g. from a backup) in place of
+    /// a live path. With `replace`, the entries of a live directory that are not
+    /// in the saved one are removed.
+    pub fn restore_entry(&mut self, saved: &Path, live_path: &str, replace: bool) -> Result<()> {
+        let staged = self.staged_path(live_path)?;
+
+        if !saved.is_dir() || saved.is_symlink() {
+            if let Some(parent) = staged.parent() {
+                fs::create_dir_all(parent).map_err(Error::io("Failed to create staging directory"))?;
+            }
+            remove_entry(&staged)?;
+            self.copy_entry(saved, &staged)?;
+
+            let change = match fs::read_link(saved) {
+                Ok(target) => StagedChange::Link(target.to_string_lossy().to_string()),
+                Err(_) => StagedChange::Copy(saved.to_path_buf()),
+            };
+            self.changes.insert(live_path.to_string(), change);
+            return Ok(());
+        }
+
+        fs::create_dir_all(&staged).map_err(Error::io("Failed to create staging directory"))?;
+        if replace {
+            for name in entry_names(&staged)? {
+                let path = staged.join(&name);
+                // Subdirectories are not sites, they are left in place
+                if saved.join(&name).symlink_metadata().is_err() && (path.is_symlink() || !path.is_dir()) {
+                    remove_entry(&path)?;
+                    self.changes.insert(format!("{}/{}", live_path, name), StagedChange::Remove);
+                }
+            }
+        }
+        for name in entry_names(saved)? {
+            self.restore_entry(&saved.join(&name), &format!("{}/{}", live_path, name), replace)?;
+        }
+        Ok(())
+    }
+
+    /// Run `nginx -t` against the staged tree.
+    /// Errors refer to the live paths of the files at fault.
+    pub fn validate(&self) -> Result<()> {
+        let root = self.root.to_string_lossy();
+        let main_config = self.staged_path(&paths().nginx_conf)?;
+        test_nginx_config(Some(&main_config.to_string_lossy()))
+            .map_err(|e| e.map_message(|m| m.replace(root.as_ref(), &paths().nginx_dir)))
+    }
+
+    /// Swap the staged changes into the live tree.
+    /// Every file and symlink is written next to its target and renamed over it,
+    /// so nginx never reads a partially written file. The replaced entries are
+    /// kept aside until the end, and put back if any change fails.
+    pub fn commit(self) -> Result<()> {
+        let mut swapped: Vec<(&Path, Option<PathBuf>)> = vec![];
+
+        for (live_path, change) in &self.changes {
+            let live = Path::new(live_path);
+            match swap_in(live, change) {
+                Ok(previous) => swapped.push((live, previous)),
+                Err(e) => {
+                    let error = match rollback(&swapped) {
+                        Ok(()) => e.map_message(|m| format!("{} (the previous files were put back)", m)),
+                        Err(rollback_error) => {
+                            e.map_message(|m| format!("{}; putting the previous files back failed too: {}", m, rollback_error))
+                        }
+                    };
+                    // The failed file was not replaced: only its temporary files remain
+                    for leftover in [swap_path(live), previous_path(live)].into_iter().flatten() {
+                        let _ = remove_entry(&leftover);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+
+        for previous in swapped.iter().filter_map(|(_, previous)| previous.as_deref()) {
+            let _ = remove_entry(previous);
+        }
+        Ok(())
+    }
+
+    /// Location of a live nginx path inside the staged tree
+    fn staged_path(&self, live_path: &str) -> Result<PathBuf> {
+        let relative = Path::new(live_path)
+            .strip_prefix(&paths().nginx_dir)
+            .map_err(|_| format!("{} is outside of {}", live_path, paths().nginx_dir))?;
+        Ok(self.root.join(relative))
+    }
+
+    /// Point absolute nginx paths of a config file to the staged tree.
+    /// Files refer to the nginx directory without the root prefix when one is set.
+    fn rewrite(&self, content: &str) -> String {
+        let paths = paths();
+        let staged = format!("{}/", self.root.to_string_lossy());
+        content
+            .replace(&format!("{}/", paths.nginx_dir), &staged)
+            .replace(&format!("{}/", paths.unrooted(&paths.nginx_dir)), &staged)
+    }
+
+    fn copy_tree(&self, src: &Path, dst: &Path) -> Result<()> {
+        fs::create_dir_all(dst).map_err(Error::io("Failed to create directory"))?;
+
+        for entry in fs::read_dir(src).map_err(Error::io(&format!("Failed to read {}", src.display())))? {
+            let entry = entry.map_err(Error::io("Failed to read entry"))?;
+            self.copy_entry(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        Ok(())
+    }
+
+    /// Copy a live file, symlink or directory into the staged tree
+    fn copy_entry(&self, src: &Path, dst: &Path) -> Result<()> {
+        let file_type = src
+            .symlink_metadata()
+            .map_err(Error::io(&format!("Failed to read {}", src.display())))?
+            .file_type();
+
+        if file_type.is_symlink() {
+            let target = fs::read_link(src)
+                .map_err(Error::io(&format!("Failed to read symlink {}", src.display())))?;
+            let target = match target.to_str() {
+                Some(t) if t.starts_with(&paths().nginx_dir) => self.staged_path(t)?,
+                _ => target,
+            };
+            symlink(&target, dst).map_err(Error::io("Failed to copy symlink"))?;
+        } else if file_type.is_dir() {
+            self.copy_tree(src, dst)?;
+        } else {
+            let bytes = fs::read(src).map_err(Error::io(&format!("Failed to read {}", src.display())))?;
+            let bytes = match String::from_utf8(bytes) {
+                Ok(text) => self.rewrite(&text).into_bytes(),
+                Err(e) => e.into_bytes(),
+            };
+            fs::write(dst, bytes).map_err(Error::io("Failed to copy file"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Apply one staged change to a live path, returning where the entry it
+/// replaced was kept
+fn swap_in(live: &Path, change: &StagedChange) -> Result<Option<PathBuf>> {
+    if !matches!(change, StagedChange::Remove)
+        && let Some(parent) = live.parent()
+    {
+        fs::create_dir_all(parent).map_err(Error::io(&format!("Failed to create {}", parent.display())))?;
+    }
+
+    let previous = match live.symlink_metadata() {
+        Ok(metadata) if !metadata.is_dir() => {
+            let previous = previous_path(live)?;
+            remove_entry(&previous)?;
+            Some(previous)
+        }
+        _ => None,
+    };
+    let swap = |tmp: &Path| -> Result<()> {
+        // The live entry stays in place until the rename, a copy is kept aside
+        if let Some(previous) = &previous {
+            copy_entry(live, previous)?;
+        }
+        fs::rename(tmp, live).map_err(Error::io(&format!("Failed to swap {}", live.display())))
+    };
+
+    match change {
+        StagedChange::Write(content) => {
+            let tmp = swap_path(live)?;
+            fs::write(&tmp, content).map_err(Error::io(&format!("Failed to write {}", tmp.display())))?;
+            if let Ok(metadata) = fs::metadata(live) {
+                let _ = fs::set_permissions(&tmp, metadata.permissions());
+            }
+            swap(&tmp)?;
+        }
+        StagedChange::Link(target) => {
+            let tmp = swap_path(live)?;
+            remove_entry(&tmp)?;
+            symlink(target, &tmp).map_err(Error::io("Failed to create symlink"))?;
+            swap(&tmp)?;
+        }
+        StagedChange::Copy(saved) => {
+            let tmp = swap_path(live)?;
+            remove_entry(&tmp)?;
+            copy_entry(saved, &tmp)?;
+            swap(&tmp)?;
+        }
+        StagedChange::Remove => {
+            if let Some(previous) = &previous {
+                fs::rename(live, previous).map_err(Error::io(&format!("Failed to remove {}", live.display())))?;
+            }
+        }
+    }
+    Ok(previous)
+}
+
+/// Put back the entries replaced by a failed commit, the last change first
+fn rollback(swapped: &[(&Path, Option<PathBuf>)]) -> Result<()> {
+    for (live, previous) in swapped.iter().rev() {
+        match previous {
+            Some(previous) => fs::rename(previous, live)
+                .map_err(Error::io(&format!("Failed to put back {}", live.display())))?,
+            None => remove_entry(live)?,
+        }
+    }
+    Ok(())
+}
+
+/// Sibling of a live path keeping its previous version during a commit
+fn previous_path(live: &Path) -> Result<PathBuf> {
+    let name = live
+        .file_name()
+        .ok_or_else(|| format!("Invalid path: {}", live.display()))?;
+    Ok(live.with_file_name(format!(".{}.xynginc-prev", name.to_string_lossy())))
+}
+
+/// Temporary sibling of a live path used for the rename swap
+fn swap_path(live: &Path) -> Result<PathBuf> {
+    let name = live
+        .file_name()
+        .ok_or_else(|| format!("Invalid path: {}", live.display()))?;
+    Ok(live.with_file_name(format!(".{}.xynginc-swap", name.to_string_lossy())))
+}
+
+/// Names of the entries of a directory, sorted
+fn entry_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(Error::io(&format!("Failed to read {}", dir.display())))? {
+        names.push(entry.map_err(Error::io("Failed to read entry"))?.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Remove a file or symlink if present
+fn remove_entry(path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path).map_err(Error::io(&format!("Failed to remove {}", path.display())))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    /// `.xynginc-prev`/`.xynginc-swap` entries left in the live site directories
+    fn leftovers() -> Vec<String> {
+        let mut names = vec![];
+        for dir in [&paths().sites_available, &paths().sites_enabled] {
+            names.extend(entry_names(Path::new(dir)).unwrap().into_iter().filter(|n| n.contains(".xynginc-")));
+        }
+        names
+    }
+
+    fn remove_site_files(name: &str) {
+        for path in layout().site_files(name) {
+            remove_entry(Path::new(&path)).unwrap();
+        }
+    }
+
+    #[test]
+    fn commit_swaps_staged_files_into_the_live_tree() {
+        let _sandbox = sandbox();
+        let site = layout().site_path("commit.test");
+        fs::write(&site, "server { listen 80; }\n").unwrap();
+
+        let mut stage = Stage::new().unwrap();
+        stage.write_site("commit.test", "server { listen 8080; }\n").unwrap();
+        stage.enable_site("commit.test").unwrap();
+        assert_eq!(fs::read_to_string(&site).unwrap(), "server { listen 80; }\n");
+        stage.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(&site).unwrap(), "server { listen 8080; }\n");
+        assert_eq!(fs::read_link(layout().switch_path("commit.test")).unwrap(), Path::new(&site));
+        assert!(leftovers().is_empty());
+        remove_site_files("commit.test");
+    }
+
+    #[test]
+    fn failed_commit_puts_the_previous_files_back() {
+        let _sandbox = sandbox();
+        let site = layout().site_path("rollback.test");
+        fs::write(&site, "server { listen 80; }\n").unwrap();
+        let blocked = format!("{}/zz-blocked", paths().nginx_dir);
+
+        let mut stage = Stage::new().unwrap();
+        stage.write_site("rollback.test", "server { listen 8080; }\n").unwrap();
+        stage.enable_site("rollback.test").unwrap();
+        stage.write_file(&format!("{}/extra.conf", blocked), "# extra\n").unwrap();
+        // Committed last, its directory can't be created over this file
+        fs::write(&blocked, "").unwrap();
+
+        let error = stage.commit().unwrap_err();
+        assert!(error.to_string().contains("the previous files were put back"), "{}", error);
+        assert_eq!(fs::read_to_string(&site).unwrap(), "server { listen 80; }\n");
+        assert!(Path::new(&layout().switch_path("rollback.test")).symlink_metadata().is_err());
+        assert!(leftovers().is_empty());
+        fs::remove_file(&blocked).unwrap();
+        remove_site_files("rollback.test");
+    }
+
+    #[test]
+    fn revert_site_restores_the_snapshot() {
+        let _sandbox = sandbox();
+        let mut stage = Stage::new().unwrap();
+        let staged_site = stage.staged_path(&layout().site_path("revert.test")).unwrap();
+        let staged_switch = stage.staged_path(&layout().switch_path("revert.test")).unwrap();
+
+        // A new site goes away entirely
+        let snapshot = stage.snapshot_site("revert.test").unwrap();
+        stage.write_site("revert.test", "server { listen 80; }\n").unwrap();
+        stage.enable_site("revert.test").unwrap();
+        stage.revert_site(snapshot).unwrap();
+        assert!(staged_site.symlink_metadata().is_err());
+        assert!(staged_switch.symlink_metadata().is_err());
+        assert!(!stage.has_changes());
+
+        // A staged site gets its previous content and change back
+        stage.write_site("revert.test", "server { listen 80; }\n").unwrap();
+        stage.enable_site("revert.test").unwrap();
+        let snapshot = stage.snapshot_site("revert.test").unwrap();
+        stage.write_site("revert.test", "server { listen 8080; }\n").unwrap();
+        stage.revert_site(snapshot).unwrap();
+        assert_eq!(fs::read_to_string(&staged_site).unwrap(), "server { listen 80; }\n");
+        assert_eq!(fs::read_link(&staged_switch).unwrap(), staged_site);
+        assert!(matches!(
+            stage.changes.get(&layout().site_path("revert.test")),
+            Some(StagedChange::Write(content)) if content == "server { listen 80; }\n"
+        ));
+    }
+
+    #[test]
+    fn dropping_a_stage_removes_its_directory() {
+        let _sandbox = sandbox();
+        let stage = Stage::new().unwrap();
+        let root = stage.root.clone();
+        assert!(root.join("nginx.conf").exists());
+        drop(stage);
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use minijinja::{Environment, ErrorKind, UndefinedBehavior};
+use serde::Serialize;
+
+use crate::mods::constants::EMBEDDED_TEMPLATES;
+use crate::mods::error::{Error, Result};
+use crate::mods::logger::{log_diff, log_info, log_step, log_success, log_warning};
+use crate::mods::paths::paths;
+use crate::mods::utils::unified_diff;
+
+/// Embedded version of a template, if the name is known
+pub fn embedded_template(name: &str) -> Option<&'static str> {
+    EMBEDDED_TEMPLATES
+        .iter()
+        .find(|(template_name, _)| *template_name == name)
+        .map(|(_, content)| *content)
+}
+
+/// Path of the local override of a template
+fn override_path(dir: &str, name: &str) -> PathBuf {
+    Path::new(dir).join(name)
+}
+
+/// Load a template through the lookup chain:
+/// local override in the template directory first, then the embedded default
+pub fn load_template(name: &str) -> Result<String> {
+    match lookup_template(name) {
+        Ok(Some(content)) => Ok(content),
+        Ok(None) => Err(Error::Template(format!("Unknown template: {}", name))),
+        Err((local, e)) => Err(Error::io(&format!("Failed to read template override {}", local.display()))(e)),
+    }
+}
+
+/// Content of a template, `None` if the name is unknown. A missing override
+/// falls back to the embedded default; any other failure to read it is
+/// returned with its path.
+fn lookup_template(name: &str) -> std::result::Result<Option<String>, (PathBuf, io::Error)> {
+    let Some(embedded) = embedded_template(name) else {
+        return Ok(None);
+    };
+
+    let local = override_path(&paths().template_dir, name);
+    match fs::read_to_string(&local) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Some(embedded.to_string())),
+        Err(e) => Err((local, e)),
+    }
+}
+
+/// Build the template environment shared by nginx configs and HTML pages.
+/// Undefined variables are errors so that no placeholder can reach a live config.
+fn build_environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_keep_trailing_newline(true);
+    env.set_loader(|name| {
+        lookup_template(name).map_err(|(local, e)| {
+            minijinja::Error::new(
+                ErrorKind::InvalidOperation,
+                format!("failed to read template override {}", local.display()),
+            )
+            .with_source(e)
+        })
+    });
+    env.add_filter("regex_escape", |value: String| regex_escape(&value));
+    env
+}
+
+/// Render a template (and its includes) with the given context
+pub fn render_template<S: Serialize>(name: &str, context: S) -> Result<String> {
+    let env = build_environment();
+    let template = env
+        .get_template(name)
+        .map_err(|e| Error::Template(format_template_error(name, &e)))?;
+
+    template
+        .render(context)
+        .map_err(|e| Error::Template(format_template_error(name, &e)))
+}
+
+/// Flatten a template error and its causes (e.g. errors raised inside includes)
+fn format_template_error(name: &str, error: &minijinja::Error) -> String {
+    let mut message = format!("Failed to render template {}: {}", name, error);
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(&format!("\n   caused by: {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+/// Escape regex metacharacters of a literal string
+pub fn regex_escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Write the embedded templates to a directory as a starting point for overrides
+pub fn export_templates(dir: Option<&str>, force: bool) -> Result<()> {
+    let dir = dir.unwrap_or(&paths().template_dir);
+    log_step(&format!("> Exporting embedded templates to {}...\n", dir));
+
+    let mut written = 0;
+    for (name, content) in EMBEDDED_TEMPLATES {
+        let path = override_path(dir, name);
+
+        if path.exists() && !force {
+            log_warning(&format!("   ⚠️  {} already exists, skipped (use --force to overwrite)", name));
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(Error::io(&format!("Failed to create directory {}", parent.display())))?;
+        }
+        fs::write(&path, content)
+            .map_err(Error::io(&format!("Failed to write template {}", path.display())))?;
+
+        log_info(&format!("   ✓ {}", name));
+        written += 1;
+    }
+
+    log_success(&format!("\n✅ {} template(s) exported", written));
+    if dir != paths().template_dir {
+        log_info(&format!("   Copy the files you want to customize to {}", paths().template_dir));
+    }
+    Ok(())
+}
+
+/// Show how local template overrides diverge from the embedded versions
+pub fn diff_templates(dir: Option<&str>) -> Result<()> {
+    let dir = dir.unwrap_or(&paths().template_dir);
+    log_step(&format!("> Comparing template overrides in {} with embedded versions...\n", dir));
+
+    let mut overridden = 0;
+    let mut changed = 0;
+    for (name, embedded) in EMBEDDED_TEMPLATES {
+        let path = override_path(dir, name);
+        if !path.is_file() {
+            continue;
+        }
+        overridden += 1;
+
+        let local = fs::read_to_string(&path)
+            .map_err(Error::io(&format!("Failed to read template override {}", path.display())))?;
+        if local == *embedded {
+            log_info(&format!("   = {} (identical to embedded)", name));
+            continue;
+        }
+
+        changed += 1;
+        log_diff(&unified_diff(
+            embedded,
+            &local,
+            &format!("embedded/{}", name),
+            &path.display().to_string(),
+        ));
+    }
+
+    for unknown in find_unknown_overrides(dir)? {
+        log_warning(&format!("   ⚠️  {} is not a known template and is ignored", unknown));
+    }
+
+    if overridden == 0 {
+        log_info("   (no local overrides, embedded templates are used)");
+    } else {
+        log_success(&format!("\n{} override(s), {} differ from the embedded version", overridden, changed));
+    }
+    Ok(())
+}
+
+/// Files in the override directory that do not match any embedded template name
+fn find_unknown_overrides(dir: &str) -> Result<Vec<String>> {
+    let mut unknown = vec![];
+    let mut pending = vec![PathBuf::from(dir)];
+
+    while let Some(current) = pending.pop() {
+        if !current.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&current).map_err(Error::io(&format!("Failed to read {}", current.display())))? {
+            let path = entry.map_err(Error::io("Failed to read entry"))?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let name = path
+                .strip_prefix(dir)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if embedded_template(&name).is_none() {
+                unknown.push(name);
+            }
+        }
+    }
+
+    unknown.sort();
+    Ok(unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::config::render_nginx_config;
+    use crate::mods::models::DomainConfig;
+    use crate::mods::testing::sandbox;
+
+    #[test]
+    fn embedded_templates_compile() {
+        let _sandbox = sandbox();
+        let env = build_environment();
+        for (name, _) in EMBEDDED_TEMPLATES {
+            if let Err(e) = env.get_template(name) {
+                panic!("{}", format_template_error(name, &e));
+            }
+        }
+    }
+
+    #[test]
+    fn undefined_variables_are_errors() {
+        let _sandbox = sandbox();
+        let error = render_template("default.conf", minijinja::context! {}).unwrap_err();
+        assert!(matches!(error, Error::Template(_)));
+        assert!(error.to_string().contains("Failed to render template default.conf"), "{}", error);
+    }
+
+    #[test]
+    fn optional_blocks_are_rendered_only_when_set() {
+        let _sandbox = sandbox();
+        let render = |value: serde_json::Value| {
+            let config: DomainConfig = serde_json::from_value(value).unwrap();
+            render_nginx_config(&config).unwrap()
+        };
+
+        let plain = render(serde_json::json!({ "domain": "example.com", "port": 3000 }));
+        assert!(!plain.contains("auth_basic"));
+        assert!(!plain.contains("Access-Control-Allow-Origin"));
+        assert!(!plain.contains("proxy_cache_path"));
+
+        let full = render(serde_json::json!({
+            "domain": "example.com",
+            "port": 3000,
+            "basic_auth": { "realm": "Staging", "user_file": "/etc/nginx/htpasswd" },
+            "cors": { "allow_origins": ["https://app.example.com"] },
+            "cache": {}
+        }));
+        assert!(full.contains("    auth_basic           \"Staging\";\n    auth_basic_user_file /etc/nginx/htpasswd;\n"));
+        assert!(full.contains(r#"if ($http_origin ~* "^(https://app\.example\.com)$") {"#));
+        assert!(full.contains("proxy_cache_path "));
+
+        for rendered in [plain, full] {
+            assert!(!rendered.contains("{{") && !rendered.contains("{%"), "{}", rendered);
+        }
+    }
+
+    #[test]
+    fn regex_escape_escapes_metacharacters() {
+        assert_eq!(regex_escape("https://a.example.com:8080"), r"https://a\.example\.com:8080");
+        assert_eq!(regex_escape(r"(a|b)+[c]{2}^$?*\"), r"\(a\|b\)\+\[c\]\{2\}\^\$\?\*\\");
+        assert_eq!(regex_escape("/api/v1"), "/api/v1");
+    }
+}
@@ -0,0 +1,43 @@
+//! Sandbox root shared by the unit tests.
+//!
+//! `paths()` and `layout()` are set once per process, so every test touching
+//! them must go through `sandbox()` before anything reads the paths.
+
+use std::fs;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use tempfile::TempDir;
+
+use crate::mods::paths::{init_paths, paths, Paths};
+
+static ROOT: OnceLock<TempDir> = OnceLock::new();
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Install the paths of a temporary root with an empty sites-enabled nginx
+/// tree, and lock it for the caller: tests changing its files can't run
+/// while another one copies or lists them
+pub fn sandbox() -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let root = ROOT.get_or_init(|| {
+        let dir = tempfile::Builder::new().prefix("xynginc-test-").tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["etc/nginx/sites-available", "etc/nginx/sites-enabled", "etc/xynginc"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        fs::write(
+            root.join("etc/nginx/nginx.conf"),
+            "events {}\nhttp {\n    include /etc/nginx/sites-enabled/*;\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("etc/xynginc/xynginc.toml"),
+            "layout = \"sites-enabled\"\nservice_manager = \"signal\"\n",
+        )
+        .unwrap();
+
+        init_paths(Paths::load(Some(root.to_str().unwrap())).unwrap()).unwrap();
+        dir
+    });
+    assert_eq!(paths().root, root.path().to_str().unwrap(), "paths() was read before sandbox()");
+    guard
+}
@@ -5,6 +5,7 @@
  */
 
 use sha2::{Sha256, Digest};
+use similar::TextDiff;
 
 /// Generates a SHA-256 hash of the domain name and returns it as a hex string.
 pub fn get_domain_hash(domain: &str) -> String {
@@ -13,3 +14,26 @@ pub fn get_domain_hash(domain: &str) -> String {
     let result = hasher.finalize();
     format!("{:x}", result)
 }
+
+/// Builds a unified diff between two texts with the given file labels.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// camelCase alias of a snake_case field name (e.g. max_body_size -> maxBodySize).
+pub fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.push(first.to_ascii_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
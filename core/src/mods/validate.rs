@@ -0,0 +1,831 @@
+use schemars::{schema_for, Schema};
+use serde::Serialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::mods::config::render_nginx_config;
+use crate::mods::error::{Error, Result};
+use crate::mods::models::{Config, DomainConfig, LoadBalancing, RouteConfig, UpstreamServer};
+use crate::mods::output::CommandReport;
+use crate::mods::utils::camel_case;
+
+/// Names that can't be used as a domain: they are taken by the default site
+/// or mean "any host" to nginx
+const RESERVED_NAMES: &[&str] = &["default", "default_server", "_"];
+
+/// Units of nginx sizes, with their multiplier
+const SIZE_UNITS: &[(char, u64)] = &[('k', 1 << 10), ('m', 1 << 20), ('g', 1 << 30)];
+
+/// Units of nginx times, longest first so that "ms" is not read as "m"
+const TIME_UNITS: &[&str] = &["ms", "s", "m", "h", "d", "w", "M", "y"];
+
+/// Problem found in a config, located by the JSON path of the offending field
+#[derive(Debug, Serialize)]
+pub struct ValidationIssue {
+    /// e.g. `domains[1].routes[0].max_body_size`
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub domains: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl CommandReport for ValidationReport {
+    fn failure(&self) -> Option<Error> {
+        (!self.issues.is_empty())
+            .then(|| Error::InvalidConfig(format!("{} problem(s) found in the config", self.issues.len())))
+    }
+}
+
+/// JSON Schema of the config file, generated from the `Config` model
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct ConfigSchema(pub Schema);
+
+impl CommandReport for ConfigSchema {}
+
+/// Aliases of the config fields whose alias is not the camelCase form of their name
+const FIELD_ALIASES: &[(&str, &[&str])] = &[("autofix_firewall", &["autoFixFirewall", "auto_fix_firewall"])];
+
+/// The schema lists the fields by their snake_case names only, and the parser
+/// also accepts their aliases: they are added so that editors don't flag them
+pub fn config_schema() -> ConfigSchema {
+    let mut schema = schema_for!(Config);
+    if let Some(object) = schema.as_object_mut() {
+        add_aliases(object);
+    }
+    ConfigSchema(schema)
+}
+
+/// Give an object schema, and every schema nested in it, the aliases of its
+/// properties and enum values
+fn add_aliases(object: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Some(serde_json::Value::Object(properties)) = object.get("properties") {
+        let mut aliases: Vec<(String, String)> = vec![];
+        for name in properties.keys() {
+            match FIELD_ALIASES.iter().find(|(field, _)| field == name) {
+                Some((_, names)) => aliases.extend(names.iter().map(|alias| (name.clone(), alias.to_string()))),
+                None if name.contains('_') => aliases.push((name.clone(), camel_case(name))),
+                None => {}
+            }
+        }
+        add_alias_properties(object, &aliases);
+    }
+    // Values of the enums (e.g. "least_conn" or "leastConn")
+    if let Some(serde_json::Value::Array(values)) = object.get_mut("enum") {
+        let aliases: Vec<serde_json::Value> = values
+            .iter()
+            .filter_map(|value| value.as_str().filter(|value| value.contains('_')))
+            .map(|value| serde_json::Value::String(camel_case(value)))
+            .collect();
+        values.extend(aliases);
+    }
+
+    let mut children: Vec<&mut serde_json::Value> = object.values_mut().collect();
+    while let Some(child) = children.pop() {
+        match child {
+            serde_json::Value::Object(nested) => add_aliases(nested),
+            serde_json::Value::Array(items) => children.extend(items.iter_mut()),
+            _ => {}
+        }
+    }
+}
+
+/// Add the aliases of an object schema as properties of the same type. A
+/// required field can then be given under any of its names.
+fn add_alias_properties(object: &mut serde_json::Map<String, serde_json::Value>, aliases: &[(String, String)]) {
+    let Some(serde_json::Value::Object(properties)) = object.get_mut("properties") else {
+        return;
+    };
+    for (name, alias) in aliases {
+        if let Some(property) = properties.get(name).cloned() {
+            properties.entry(alias.clone()).or_insert(property);
+        }
+    }
+
+    let Some(serde_json::Value::Array(required)) = object.get_mut("required") else {
+        return;
+    };
+    let mut any_of = vec![];
+    required.retain(|name| {
+        let names: Vec<&str> = aliases
+            .iter()
+            .filter(|(field, _)| name == field.as_str())
+            .map(|(_, alias)| alias.as_str())
+            .collect();
+        if names.is_empty() {
+            return true;
+        }
+        let alternatives = std::iter::once(name.as_str().unwrap_or_default())
+            .chain(names)
+            .map(|field| serde_json::json!({ "required": [field] }))
+            .collect::<Vec<_>>();
+        any_of.push(serde_json::json!({ "anyOf": alternatives }));
+        false
+    });
+    if any_of.is_empty() {
+        return;
+    }
+    if required.is_empty() {
+        object.remove("required");
+    }
+    object.insert("allOf".to_string(), serde_json::Value::Array(any_of));
+}
+
+/// Every problem of a parsed config, empty when it can be applied
+pub fn validate(config: &Config) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    let mut names: Vec<String> = vec![];
+
+    for (index, domain) in config.domains.iter().enumerate() {
+        let field = format!("domains[{}]", index);
+        let before = issues.len();
+        validate_domain(domain, &field, &mut issues);
+
+        // Compare canonical names: "Exämple.com" and "xn--exmple-cua.com" are the same site
+        let name = normalize_domain(&domain.domain).unwrap_or_else(|_| domain.domain.clone());
+        if names.contains(&name) {
+            issues.push(ValidationIssue {
+                field: format!("{}.domain", field),
+                message: format!("duplicate domain '{}'", name),
+            });
+        }
+        names.push(name.clone());
+
+        // Errors of the templates themselves (e.g. an invalid override),
+        // only meaningful once the fields above are sound
+        let normalized = DomainConfig { domain: name, ..domain.clone() };
+        if issues.len() == before
+            && let Err(e) = render_nginx_config(&normalized)
+        {
+            issues.push(ValidationIssue {
+                field,
+                message: e.message().to_string(),
+            });
+        }
+    }
+
+    issues
+}
+
+/// Validated copy of `config`, with the domain names in canonical form
+/// (lowercase, IDNs converted to punycode)
+pub fn check_config(config: &Config) -> Result<Config> {
+    let issues = validate(config);
+    if !issues.is_empty() {
+        return Err(issues_error(&issues));
+    }
+
+    let mut checked = config.clone();
+    for domain in &mut checked.domains {
+        domain.domain = normalize_domain(&domain.domain)?;
+    }
+    Ok(checked)
+}
+
+/// Validated copy of a single domain, with its name in canonical form
+pub fn check_domain_config(config: &DomainConfig) -> Result<DomainConfig> {
+    let mut issues = vec![];
+    validate_domain(config, "", &mut issues);
+    if !issues.is_empty() {
+        return Err(issues_error(&issues));
+    }
+
+    let checked = DomainConfig {
+        domain: normalize_domain(&config.domain)?,
+        ..config.clone()
+    };
+    render_nginx_config(&checked)?;
+    Ok(checked)
+}
+
+/// Validated copy of each domain of `config`, in order. A domain whose name
+/// appeared earlier in the config is rejected as a duplicate.
+pub fn check_domains(config: &Config) -> Vec<Result<DomainConfig>> {
+    let mut names: Vec<String> = vec![];
+    config
+        .domains
+        .iter()
+        .map(|domain| {
+            let checked = check_domain_config(domain)?;
+            if names.contains(&checked.domain) {
+                return Err(Error::InvalidConfig(format!("domain: duplicate domain '{}'", checked.domain)));
+            }
+            names.push(checked.domain.clone());
+            Ok(checked)
+        })
+        .collect()
+}
+
+/// Copy of `config` with every valid domain name in canonical form, the
+/// invalid ones left as written, so that their sites can still be recognized
+pub fn canonical_names(config: &Config) -> Config {
+    Config {
+        domains: config
+            .domains
+            .iter()
+            .map(|d| DomainConfig {
+                domain: normalize_domain(&d.domain).unwrap_or_else(|_| d.domain.clone()),
+                ..d.clone()
+            })
+            .collect(),
+        ..config.clone()
+    }
+}
+
+fn issues_error(issues: &[ValidationIssue]) -> Error {
+    let lines: Vec<String> = issues
+        .iter()
+        .map(|issue| format!("{}: {}", issue.field, issue.message))
+        .collect();
+    match lines.as_slice() {
+        [line] => Error::InvalidConfig(line.clone()),
+        _ => Error::InvalidConfig(format!(
+            "{} problems:\n   {}",
+            lines.len(),
+            lines.join("\n   ")
+        )),
+    }
+}
+
+fn validate_domain(domain: &DomainConfig, field: &str, issues: &mut Vec<ValidationIssue>) {
+    let mut issue = |name: &str, result: Result<()>| {
+        if let Err(e) = result {
+            issues.push(ValidationIssue {
+                field: if field.is_empty() { name.to_string() } else { format!("{}.{}", field, name) },
+                message: e.message().to_string(),
+            })
+        }
+    };
+
+    let name = normalize_domain(&domain.domain);
+    let wildcard = name.as_ref().is_ok_and(|name| name.starts_with("*."));
+    issue("domain", name.map(|_| ()));
+
+    if domain.ssl {
+        match domain.email.as_deref() {
+            Some(email) => issue("email", check_email(email)),
+            None => issue("email", invalid("an email is required for Let's Encrypt when ssl is enabled")),
+        }
+        if wildcard {
+            issue(
+                "ssl",
+                invalid("wildcard certificates need a DNS challenge, ssl is not supported on wildcard domains"),
+            );
+        }
+    }
+    // The host is also the one of the routes that only set a port
+    let routes_use_host = domain.routes.iter().any(|route| route.port.is_some() && route.host.is_none());
+    if domain.upstreams.is_empty() || routes_use_host {
+        issue("host", check_host(&domain.host));
+    }
+    issue("port", check_port(domain.port));
+    issue("max_body_size", check_size(&domain.max_body_size));
+    if let Some(hash_key) = &domain.hash_key {
+        issue("hash_key", check_argument(hash_key));
+    }
+    for (index, upstream) in domain.upstreams.iter().enumerate() {
+        check_upstream(upstream, &format!("upstreams[{}]", index), &mut issue);
+    }
+    issue(
+        "upstreams",
+        domain
+            .upstream_servers()
+            .and_then(|servers| check_pool(&servers, domain.load_balancing, domain.hash_key.as_deref())),
+    );
+
+    for (index, route) in domain.routes.iter().enumerate() {
+        let route_field = format!("routes[{}]", index);
+        let route_issue = |name: &str| format!("{}.{}", route_field, name);
+
+        // Regex paths and rewrites are written quoted, prefix paths bare
+        let path = if route.regex { check_quoted(&route.path) } else { check_argument(&route.path) };
+        issue(&route_issue("path"), path.and_then(|_| check_route_path(route)));
+        if domain.routes[..index].iter().any(|other| other.regex == route.regex && other.path == route.path) {
+            issue(&route_issue("path"), invalid("duplicate route"));
+        }
+        if route.strip_prefix && route.regex {
+            issue(&route_issue("strip_prefix"), invalid("'strip_prefix' is only supported on prefix routes"));
+        }
+        if let Some(rewrite) = &route.rewrite {
+            issue(&route_issue("rewrite.pattern"), check_quoted(&rewrite.pattern));
+            issue(&route_issue("rewrite.replacement"), check_quoted(&rewrite.replacement));
+        }
+        if let Some(host) = &route.host {
+            issue(&route_issue("host"), check_host(host));
+        }
+        issue(&route_issue("port"), check_port(route.port));
+        if let Some(size) = &route.max_body_size {
+            issue(&route_issue("max_body_size"), check_size(size));
+        }
+        if let Some(hash_key) = &route.hash_key {
+            issue(&route_issue("hash_key"), check_argument(hash_key));
+        }
+        for (name, timeout) in [
+            ("connect_timeout", &route.connect_timeout),
+            ("send_timeout", &route.send_timeout),
+            ("read_timeout", &route.read_timeout),
+        ] {
+            if let Some(timeout) = timeout {
+                issue(&route_issue(name), check_time(timeout));
+            }
+        }
+        for (upstream_index, upstream) in route.upstreams.iter().enumerate() {
+            check_upstream(upstream, &format!("{}.upstreams[{}]", route_field, upstream_index), &mut issue);
+        }
+        issue(
+            &route_issue("upstreams"),
+            route.upstream_servers(&domain.host).and_then(|servers| match servers {
+                Some(servers) => check_pool(&servers, route.load_balancing, route.hash_key.as_deref()),
+                None => Ok(()),
+            }),
+        );
+    }
+
+    if let Some(cors) = &domain.cors {
+        for (index, method) in cors.allow_methods.iter().enumerate() {
+            issue(&format!("cors.allow_methods[{}]", index), check_token(method));
+        }
+        for (index, header) in cors.allow_headers.iter().enumerate() {
+            issue(&format!("cors.allow_headers[{}]", index), check_header_value(header));
+        }
+        if cors.allow_origins.is_empty() {
+            issue("cors.allow_origins", invalid("at least one origin is required"));
+        }
+        for (index, origin) in cors.allow_origins.iter().enumerate() {
+            issue(&format!("cors.allow_origins[{}]", index), check_header_value(origin));
+        }
+        if cors.allow_credentials && cors.allow_origins.iter().any(|origin| origin == "*") {
+            issue("cors.allow_credentials", invalid("credentials cannot be allowed with the '*' origin"));
+        }
+    }
+    if let Some(auth) = &domain.basic_auth {
+        issue("basic_auth.realm", check_header_value(&auth.realm));
+        issue("basic_auth.user_file", check_argument(&auth.user_file));
+    }
+    if let Some(cache) = &domain.cache {
+        issue("cache.valid", check_time(&cache.valid));
+        issue("cache.max_size", check_size(&cache.max_size));
+        issue("cache.inactive", check_time(&cache.inactive));
+    }
+}
+
+fn check_upstream(upstream: &UpstreamServer, field: &str, issue: &mut impl FnMut(&str, Result<()>)) {
+    issue(&format!("{}.host", field), check_host(&upstream.host));
+    issue(&format!("{}.port", field), check_port(Some(upstream.port)));
+    issue(&format!("{}.fail_timeout", field), check_time(&upstream.fail_timeout));
+}
+
+/// Check an upstream pool against its balancing method
+fn check_pool(servers: &[UpstreamServer], load_balancing: LoadBalancing, hash_key: Option<&str>) -> Result<()> {
+    if load_balancing == LoadBalancing::Hash && hash_key.is_none_or(|key| key.trim().is_empty()) {
+        return invalid("'hash_key' is required with the hash balancing method");
+    }
+    // nginx rejects the backup flag with hash based methods
+    let hash_based = matches!(load_balancing, LoadBalancing::IpHash | LoadBalancing::Hash);
+    if hash_based && servers.iter().any(|s| s.backup) {
+        return Err(Error::InvalidConfig(format!(
+            "backup servers cannot be used with the {:?} balancing method",
+            load_balancing
+        )));
+    }
+    if servers.iter().all(|s| s.backup) {
+        return invalid("the upstream pool needs at least one non-backup server");
+    }
+    if servers.iter().any(|s| s.weight == 0) {
+        return invalid("upstream weight must be at least 1");
+    }
+    Ok(())
+}
+
+/// Check where a route path can be used, its characters being checked already
+fn check_route_path(route: &RouteConfig) -> Result<()> {
+    if route.path.is_empty() {
+        return invalid("path cannot be empty");
+    }
+    if !route.regex {
+        if !route.path.starts_with('/') {
+            return invalid("prefix paths must start with '/'");
+        }
+        if route.path == "/" {
+            return invalid("'/' is served by the domain backend, use a more specific path");
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> Result<()> {
+    Err(Error::InvalidConfig(message.to_string()))
+}
+
+/// Canonical form of a domain name, used for `server_name` and as a file name.
+///
+/// Accepts RFC 1123 host names, internationalized names (converted to
+/// punycode), a leading wildcard label (`*.example.com`) and IP literals
+/// (`192.0.2.1`, `[2001:db8::1]`). Reserved names such as `default` are rejected.
+pub fn normalize_domain(name: &str) -> Result<String> {
+    let invalid = |reason: String| Error::InvalidConfig(format!("invalid domain '{}': {}", printable(name), reason));
+
+    if name.is_empty() {
+        return Err(invalid("it is empty".to_string()));
+    }
+    if let Some(c) = name.chars().find(|&c| is_forbidden(c) || matches!(c, '/' | '\\')) {
+        return Err(invalid(format!("forbidden character '{}'", printable(&c.to_string()))));
+    }
+    if RESERVED_NAMES.contains(&name.to_lowercase().as_str()) {
+        return Err(invalid("this name is reserved for the default site".to_string()));
+    }
+
+    // IP literals
+    if let Ok(ip) = name.parse::<Ipv4Addr>() {
+        return Ok(ip.to_string());
+    }
+    if let Some(ip) = name.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        return ip
+            .parse::<Ipv6Addr>()
+            .map(|ip| format!("[{}]", ip))
+            .map_err(|_| invalid("not a valid IPv6 address".to_string()));
+    }
+    if name.parse::<Ipv6Addr>().is_ok() {
+        return Err(invalid(format!("IPv6 addresses must be written in brackets: [{}]", name)));
+    }
+
+    let (wildcard, host) = match name.strip_prefix("*.") {
+        Some(rest) => (true, rest),
+        None => (false, name),
+    };
+    if host.contains('*') {
+        return Err(invalid("a wildcard is only allowed as the whole first label (e.g. *.example.com)".to_string()));
+    }
+
+    let ascii = if host.is_ascii() {
+        host.to_ascii_lowercase()
+    } else {
+        idna::domain_to_ascii_strict(host)
+            .map_err(|_| invalid("not a valid internationalized domain name".to_string()))?
+    };
+    check_host_name(&ascii).map_err(invalid)?;
+
+    if wildcard {
+        if !ascii.contains('.') {
+            return Err(invalid("a wildcard must cover a registered name (e.g. *.example.com)".to_string()));
+        }
+        Ok(format!("*.{}", ascii))
+    } else {
+        Ok(ascii)
+    }
+}
+
+/// RFC 1123 rules on an ASCII host name, returning the reason of the failure
+fn check_host_name(name: &str) -> std::result::Result<(), String> {
+    if name.len() > 253 {
+        return Err("longer than 253 characters".to_string());
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err("empty label (leading, trailing or consecutive dots)".to_string());
+        }
+        if label.len() > 63 {
+            return Err(format!("label '{}' is longer than 63 characters", label));
+        }
+        if let Some(c) = label.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-') {
+            return Err(format!("character '{}' is not allowed, only letters, digits and '-'", c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label '{}' starts or ends with '-'", label));
+        }
+    }
+    if name.rsplit('.').next().is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit())) {
+        return Err("the last label is numeric but the name is not an IPv4 address".to_string());
+    }
+    Ok(())
+}
+
+/// Check a backend address: host name, IPv4 or bracketed IPv6
+pub fn check_host(host: &str) -> Result<()> {
+    let invalid = |reason: String| Error::InvalidConfig(format!("invalid host '{}': {}", printable(host), reason));
+
+    if host.is_empty() {
+        return Err(invalid("it is empty".to_string()));
+    }
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(());
+    }
+    if let Some(ip) = host.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        return ip
+            .parse::<Ipv6Addr>()
+            .map(|_| ())
+            .map_err(|_| invalid("not a valid IPv6 address".to_string()));
+    }
+    if host.parse::<Ipv6Addr>().is_ok() {
+        return Err(invalid(format!("IPv6 addresses must be written in brackets: [{}]", host)));
+    }
+    check_host_name(host).map_err(invalid)
+}
+
+fn check_port(port: Option<u16>) -> Result<()> {
+    match port {
+        Some(0) => invalid("port must be between 1 and 65535"),
+        _ => Ok(()),
+    }
+}
+
+fn check_email(email: &str) -> Result<()> {
+    let valid = email
+        .split_once('@')
+        .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'))
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control());
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidConfig(format!("invalid email '{}'", printable(email))))
+    }
+}
+
+/// Check an nginx size: a number optionally followed by k, m or g (e.g. "512", "20M", "1g")
+pub fn check_size(size: &str) -> Result<()> {
+    let invalid = || {
+        Error::InvalidConfig(format!(
+            "invalid size '{}', expected a number optionally followed by k, m or g (e.g. 20M)",
+            printable(size)
+        ))
+    };
+
+    let (digits, multiplier) = match size.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some(unit) if !unit.is_ascii_digit() => {
+            let (_, multiplier) = SIZE_UNITS.iter().find(|(u, _)| *u == unit).ok_or_else(invalid)?;
+            (&size[..size.len() - 1], *multiplier)
+        }
+        _ => (size, 1),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    // nginx stores sizes in a signed 64-bit offset
+    let fits = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .is_some_and(|bytes| bytes <= i64::MAX as u64);
+    if !fits {
+        return Err(Error::InvalidConfig(format!("size '{}' is too large", size)));
+    }
+    Ok(())
+}
+
+/// Check an nginx time: numbers each followed by a unit, the last one
+/// defaulting to seconds (e.g. "30", "500ms", "1h30m")
+pub fn check_time(time: &str) -> Result<()> {
+    let invalid = || {
+        Error::InvalidConfig(format!(
+            "invalid time '{}', expected e.g. 30s, 500ms or 1h30m (units: ms s m h d w M y)",
+            printable(time)
+        ))
+    };
+
+    let mut rest = time;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return Err(invalid());
+        }
+        rest = &rest[digits..];
+        if rest.is_empty() {
+            break;
+        }
+        let unit = TIME_UNITS.iter().find(|unit| rest.starts_with(*unit)).ok_or_else(invalid)?;
+        rest = &rest[unit.len()..];
+    }
+    Ok(())
+}
+
+/// Characters that end or escape an nginx directive
+fn is_forbidden(c: char) -> bool {
+    c.is_control() || c.is_whitespace() || matches!(c, ';' | '{' | '}' | '"' | '\'' | '#' | '$')
+}
+
+/// Check a value written unquoted as a directive argument (file path, hash key)
+fn check_argument(value: &str) -> Result<()> {
+    if value.is_empty() {
+        return invalid("value cannot be empty");
+    }
+    // Variables are legitimate here (e.g. hash $request_uri)
+    match value.chars().find(|&c| c != '$' && is_forbidden(c)) {
+        Some(c) => Err(Error::InvalidConfig(format!(
+            "forbidden character '{}' in '{}'",
+            printable(&c.to_string()),
+            printable(value)
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Check a value written between double quotes. Backslashes are kept for
+/// regexes (e.g. `\.php$`), except an unpaired one at the end that would
+/// escape the closing quote.
+fn check_quoted(value: &str) -> Result<()> {
+    if let Some(c) = value.chars().find(|&c| c.is_control() || c == '"') {
+        return Err(Error::InvalidConfig(format!(
+            "forbidden character '{}' in '{}'",
+            printable(&c.to_string()),
+            printable(value)
+        )));
+    }
+    let trailing = value.len() - value.trim_end_matches('\\').len();
+    if trailing % 2 == 1 {
+        return Err(Error::InvalidConfig(format!(
+            "'{}' ends with an unpaired '\\' that would escape the closing quote",
+            printable(value)
+        )));
+    }
+    Ok(())
+}
+
+/// Check a quoted value sent as is to clients (header, realm), where nginx
+/// would expand `$` into a variable
+fn check_header_value(value: &str) -> Result<()> {
+    check_quoted(value)?;
+    if value.contains('$') {
+        return Err(Error::InvalidConfig(format!(
+            "forbidden character '$' in '{}', nginx would read it as a variable",
+            printable(value)
+        )));
+    }
+    Ok(())
+}
+
+/// Check an HTTP token (RFC 9110), such as a method name. `$` is a token
+/// character but would be expanded by nginx, it is left out.
+fn check_token(value: &str) -> Result<()> {
+    if value.is_empty() {
+        return invalid("value cannot be empty");
+    }
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#%&'*+-.^_`|~".contains(c);
+    match value.chars().find(|&c| !is_tchar(c)) {
+        Some(c) => Err(Error::InvalidConfig(format!(
+            "forbidden character '{}' in '{}'",
+            printable(&c.to_string()),
+            printable(value)
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Value as shown in an error message, control characters escaped
+fn printable(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { c.escape_debug().to_string() } else { c.to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::testing::sandbox;
+
+    fn domain(value: serde_json::Value) -> DomainConfig {
+        let mut config = serde_json::json!({ "domain": "example.com", "port": 3000 });
+        config.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn issue_fields(config: &DomainConfig) -> Vec<String> {
+        let mut issues = vec![];
+        validate_domain(config, "", &mut issues);
+        issues.into_iter().map(|issue| issue.field).collect()
+    }
+
+    #[test]
+    fn rejects_injected_cors_methods_and_headers() {
+        let config = domain(serde_json::json!({
+            "cors": {
+                "allow_methods": ["GET", "POST\"; include /etc/shadow; #"],
+                "allow_headers": ["Content-Type", "X\"; include /etc/shadow; #", "X\\"]
+            }
+        }));
+        assert_eq!(
+            issue_fields(&config),
+            ["cors.allow_methods[1]", "cors.allow_headers[1]", "cors.allow_headers[2]"]
+        );
+    }
+
+    #[test]
+    fn rejects_variables_in_header_values() {
+        let config = domain(serde_json::json!({
+            "cors": {
+                "allow_methods": ["GET", "$request_method"],
+                "allow_headers": ["X-Token-$http_authorization"],
+                "allow_origins": ["https://$host"]
+            },
+            "basic_auth": { "realm": "Restricted $remote_addr", "user_file": "/etc/nginx/.htpasswd" }
+        }));
+        assert_eq!(
+            issue_fields(&config),
+            [
+                "cors.allow_methods[1]",
+                "cors.allow_headers[0]",
+                "cors.allow_origins[0]",
+                "basic_auth.realm"
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_default_cors() {
+        let config = domain(serde_json::json!({ "cors": { "allow_origins": ["https://app.example.com"] } }));
+        assert!(issue_fields(&config).is_empty());
+    }
+
+    #[test]
+    fn rejects_injected_route_paths() {
+        let config = domain(serde_json::json!({
+            "routes": [
+                { "path": "/api;include /etc/shadow", "port": 4000 },
+                { "path": "^/v1/\\", "regex": true, "port": 4001 },
+                { "path": "/ok", "port": 4002 },
+                { "path": "^/v[0-9]+/", "regex": true, "port": 4003 }
+            ]
+        }));
+        assert_eq!(issue_fields(&config), ["routes[0].path", "routes[1].path"]);
+    }
+
+    #[test]
+    fn rejects_injected_rewrites() {
+        let config = domain(serde_json::json!({
+            "routes": [{
+                "path": "/api",
+                "rewrite": { "pattern": "^/api/(.*)\\", "replacement": "/$1\" break; include /etc/shadow; #" }
+            }]
+        }));
+        assert_eq!(
+            issue_fields(&config),
+            ["routes[0].rewrite.pattern", "routes[0].rewrite.replacement"]
+        );
+    }
+
+    #[test]
+    fn accepts_escaped_regex_routes_and_rewrites() {
+        let _sandbox = sandbox();
+        let config = domain(serde_json::json!({
+            "routes": [
+                { "path": "\\.php$", "regex": true, "port": 4000 },
+                {
+                    "path": "^/items/\\d+",
+                    "regex": true,
+                    "port": 4001,
+                    "rewrite": { "pattern": "^/items/(\\d+)\\.json$", "replacement": "/api/items/$1\\\\" }
+                }
+            ]
+        }));
+        assert!(issue_fields(&config).is_empty());
+        let rendered = render_nginx_config(&check_domain_config(&config).unwrap()).unwrap();
+        assert!(rendered.contains(r#"location ~ "\.php$""#));
+        assert!(rendered.contains(r#"rewrite "^/items/(\d+)\.json$" "/api/items/$1\\" break;"#));
+    }
+
+    #[test]
+    fn injected_config_is_not_rendered() {
+        let config = domain(serde_json::json!({
+            "routes": [{ "path": "^/x\\", "regex": true, "port": 4000 }]
+        }));
+        assert!(check_domain_config(&config).is_err());
+    }
+
+    #[test]
+    fn rejects_routes_pools_and_cors_the_generator_cannot_render() {
+        let config = domain(serde_json::json!({
+            "load_balancing": "hash",
+            "routes": [
+                { "path": "/", "port": 4000 },
+                { "path": "api", "port": 4001 },
+                { "path": "/v1", "port": 4002 },
+                { "path": "/v1", "port": 4003 },
+                { "path": "^/v2", "regex": true, "strip_prefix": true, "port": 4004 },
+                { "path": "/v3", "upstreams": [{ "host": "localhost", "port": 4005, "backup": true }] }
+            ],
+            "cors": { "allow_origins": ["*"], "allow_credentials": true }
+        }));
+        assert_eq!(
+            issue_fields(&config),
+            [
+                "upstreams",
+                "routes[0].path",
+                "routes[1].path",
+                "routes[3].path",
+                "routes[4].strip_prefix",
+                "routes[5].upstreams",
+                "cors.allow_credentials",
+            ]
+        );
+
+        let config = domain(serde_json::json!({ "cors": { "allow_origins": [] } }));
+        assert_eq!(issue_fields(&config), ["cors.allow_origins"]);
+    }
+}
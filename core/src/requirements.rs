@@ -1,4 +1,4 @@
-/**
+/*!
  * Requirements Management Module
  * 
  * This module handles the installation and configuration of system requirements
@@ -11,15 +11,18 @@ use std::path::Path;
 use std::process::{Command, Stdio};
 use std::io::Write;
 
-use crate::mods::nginx_modules;
+use xynginc::mods::layout::{layout, Layout};
+use xynginc::mods::nginx_modules;
+use xynginc::mods::paths::paths;
+use xynginc::mods::service::service_manager;
 
 /// System requirements that XyNginC needs to function
 #[derive(Debug, Clone)]
 pub struct SystemRequirements {
     pub nginx: bool,
     pub certbot: bool,
-    pub sites_available_dir: bool,
-    pub sites_enabled_dir: bool,
+    /// Site directories of the layout (sites-available/sites-enabled or conf.d)
+    pub site_dirs: bool,
     pub backup_dir: bool,
     pub headers_more_module: bool,
 }
@@ -31,8 +34,7 @@ pub fn check_missing_requirements() -> Result<SystemRequirements, String> {
     let mut requirements = SystemRequirements {
         nginx: false,
         certbot: false,
-        sites_available_dir: false,
-        sites_enabled_dir: false,
+        site_dirs: false,
         backup_dir: false,
         headers_more_module: false,
     };
@@ -58,7 +60,7 @@ pub fn check_missing_requirements() -> Result<SystemRequirements, String> {
             
             // Check if nginx plugin is available
             let plugins_output = Command::new("certbot")
-                .args(&["plugins", "--text"])
+                .args(["plugins", "--text"])
                 .output();
             
             let has_nginx_plugin = if let Ok(plugins) = plugins_output {
@@ -82,24 +84,17 @@ pub fn check_missing_requirements() -> Result<SystemRequirements, String> {
     }
 
     // Check directories
-    let sites_available = "/etc/nginx/sites-available";
-    let sites_enabled = "/etc/nginx/sites-enabled";
-    let backup_dir = "/var/backups/xynginc";
-
-    print!("   nginx sites-available: ");
-    if Path::new(sites_available).exists() {
-        println!("✓ {}", sites_available);
-        requirements.sites_available_dir = true;
-    } else {
-        println!("❌ Not found");
-    }
-
-    print!("   nginx sites-enabled:   ");
-    if Path::new(sites_enabled).exists() {
-        println!("✓ {}", sites_enabled);
-        requirements.sites_enabled_dir = true;
-    } else {
-        println!("❌ Not found");
+    let backup_dir = &paths().backup_dir;
+
+    requirements.site_dirs = true;
+    for dir in layout().site_dirs() {
+        print!("   nginx {:<16} ", format!("{}:", dir.name));
+        if Path::new(&dir.path).exists() {
+            println!("✓ {}", dir.path);
+        } else {
+            println!("❌ Not found");
+            requirements.site_dirs = false;
+        }
     }
 
     print!("   backup directory:      ");
@@ -177,7 +172,7 @@ fn fix_apt_repositories() -> Result<(), String> {
     println!("> Step 1: Backing up current sources...");
     if Path::new("/etc/apt/sources.list.d").exists() {
         let output = Command::new("cp")
-            .args(&["-r", "/etc/apt/sources.list.d", &backup_dir])
+            .args(["-r", "/etc/apt/sources.list.d", &backup_dir])
             .output()
             .map_err(|e| format!("Failed to backup sources: {}", e))?;
         
@@ -207,22 +202,20 @@ fn fix_apt_repositories() -> Result<(), String> {
     
     // Scan for files containing problematic URLs
     if let Ok(entries) = fs::read_dir("/etc/apt/sources.list.d") {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("list") {
-                    if let Ok(content) = fs::read_to_string(&path) {
-                        let has_problems = content.contains("ftp.postgresql.org") ||
-                                          content.contains("download.docker.com/linux/ubuntu") ||
-                                          content.contains("apt.llvm.org");
-                        
-                        if has_problems {
-                            let disabled_path = format!("{}.disabled", path.display());
-                            println!("   → Disabling {:?}", path.file_name().unwrap());
-                            fs::rename(&path, &disabled_path)
-                                .map_err(|e| format!("Failed to disable {:?}: {}", path, e))?;
-                        }
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("list")
+                && let Ok(content) = fs::read_to_string(&path)
+            {
+                let has_problems = content.contains("ftp.postgresql.org") ||
+                                  content.contains("download.docker.com/linux/ubuntu") ||
+                                  content.contains("apt.llvm.org");
+                
+                if has_problems {
+                    let disabled_path = format!("{}.disabled", path.display());
+                    println!("   → Disabling {:?}", path.file_name().unwrap());
+                    fs::rename(&path, &disabled_path)
+                        .map_err(|e| format!("Failed to disable {:?}: {}", path, e))?;
                 }
             }
         }
@@ -295,25 +288,22 @@ pub fn install_missing_requirements(requirements: &SystemRequirements) -> Result
     }
 
     // Create missing directories first
-    if !requirements.sites_available_dir {
-        println!("> Creating sites-available directory...");
-        Command::new("mkdir")
-            .args(&["-p", "/etc/nginx/sites-available"])
-            .status()
-            .map_err(|e| format!("Failed to create sites-available directory: {}", e))?;
-    }
-
-    if !requirements.sites_enabled_dir {
-        println!("> Creating sites-enabled directory...");
-        Command::new("mkdir")
-            .args(&["-p", "/etc/nginx/sites-enabled"])
-            .status()
-            .map_err(|e| format!("Failed to create sites-enabled directory: {}", e))?;
+    if !requirements.site_dirs {
+        for dir in layout().site_dirs() {
+            if Path::new(&dir.path).exists() {
+                continue;
+            }
+            println!("> Creating {} directory...", dir.name);
+            Command::new("mkdir")
+                .args(["-p", &dir.path])
+                .status()
+                .map_err(|e| format!("Failed to create {} directory: {}", dir.name, e))?;
+        }
     }
 
     if !requirements.backup_dir {
         println!("> Creating backup directory...");
-        fs::create_dir_all("/var/backups/xynginc")
+        fs::create_dir_all(&paths().backup_dir)
             .map_err(|e| format!("Failed to create backup directory: {}", e))?;
     }
 
@@ -383,7 +373,7 @@ pub fn install_missing_requirements(requirements: &SystemRequirements) -> Result
 
     // Install headers-more module if needed
     if !requirements.headers_more_module {
-        nginx_modules::install_headers_more_module()?;
+        nginx_modules::install_headers_more_module().map_err(|e| e.to_string())?;
     }
 
     println!("\n✅ All requirements installed and configured successfully!");
@@ -395,33 +385,23 @@ fn configure_nginx() -> Result<(), String> {
     println!("\n>  Configuring nginx...");
 
     // Ensure nginx service is enabled and started
-    let output = Command::new("systemctl")
-        .args(&["enable", "nginx"])
-        .output()
-        .map_err(|e| format!("Failed to enable nginx service: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Failed to enable nginx service: {}", stderr));
-    }
-
-    let output = Command::new("systemctl")
-        .args(&["start", "nginx"])
-        .output()
-        .map_err(|e| format!("Failed to start nginx service: {}", e))?;
+    let manager = service_manager();
+    println!("   > Using {} to manage nginx", manager.kind().name());
+    manager.enable().map_err(|e| e.to_string())?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    if !manager.is_active().map_err(|e| e.to_string())?
+        && let Err(e) = manager.start()
         // Don't fail if nginx is already running
-        if !stderr.contains("already") {
-            return Err(format!("Failed to start nginx service: {}", stderr));
-        }
+        && !e.message().contains("already")
+    {
+        return Err(e.to_string());
     }
 
     // Create or update nginx configuration to include sites-enabled
-    let nginx_conf_path = "/etc/nginx/nginx.conf";
+    // (the conf.d layout relies on the include shipped by the distribution)
+    let nginx_conf_path = &paths().nginx_conf;
     
-    if Path::new(nginx_conf_path).exists() {
+    if layout() == Layout::SitesEnabled && Path::new(nginx_conf_path).exists() {
         let conf_content = fs::read_to_string(nginx_conf_path)
             .map_err(|e| format!("Failed to read nginx.conf: {}", e))?;
 
@@ -437,7 +417,10 @@ fn configure_nginx() -> Result<(), String> {
                 updated_content.push('\n');
                 
                 if line.trim() == "http {" {
-                    updated_content.push_str("    include /etc/nginx/sites-enabled/*;\n");
+                    updated_content.push_str(&format!(
+                        "    include {}/*;\n",
+                        paths().unrooted(&paths().sites_enabled)
+                    ));
                 }
             }
 
@@ -457,15 +440,7 @@ fn configure_nginx() -> Result<(), String> {
             }
 
             // Reload nginx
-            let output = Command::new("systemctl")
-                .args(&["reload", "nginx"])
-                .output()
-                .map_err(|e| format!("Failed to reload nginx: {}", e))?;
-
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(format!("Failed to reload nginx: {}", stderr));
-            }
+            manager.reload().map_err(|e| e.to_string())?;
         }
     }
 
@@ -486,8 +461,7 @@ pub fn interactive_install() -> Result<(), String> {
     let missing_count = [
         !requirements.nginx,
         !requirements.certbot,
-        !requirements.sites_available_dir,
-        !requirements.sites_enabled_dir,
+        !requirements.site_dirs,
         !requirements.backup_dir,
         !requirements.headers_more_module,
     ].iter().filter(|&&x| x).count();
@@ -506,7 +480,7 @@ pub fn interactive_install() -> Result<(), String> {
     if !requirements.certbot {
         install_list.push_str("certbot ");
     }
-    if !requirements.sites_available_dir || !requirements.sites_enabled_dir {
+    if !requirements.site_dirs {
         install_list.push_str("nginx directories ");
     }
     if !requirements.backup_dir {
@@ -555,7 +529,7 @@ pub fn interactive_install() -> Result<(), String> {
     let final_check = check_missing_requirements()?;
     
     let all_satisfied = final_check.nginx && final_check.certbot && 
-                       final_check.sites_available_dir && final_check.sites_enabled_dir &&
+                       final_check.site_dirs &&
                        final_check.headers_more_module;
     
     if all_satisfied {
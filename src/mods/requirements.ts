@@ -101,16 +101,16 @@ export async function applyConfig(
       if (pwdMatch) {
         const pwd = pwdMatch[1];
         await execStream(
-          `(echo '${pwd}'; echo '${configJson}') | sudo -S ${binaryPath} apply --config -`,
+          `(echo '${pwd}'; echo '${configJson}') | sudo -S ${binaryPath} apply --config - --format json`,
         );
       } else {
         await execStream(
-          `echo '${configJson}' | ${sudoCmd} ${binaryPath} apply --config -`,
+          `echo '${configJson}' | ${sudoCmd} ${binaryPath} apply --config - --format json`,
         );
       }
     } else {
       await execStream(
-        `echo '${configJson}' | ${sudoCmd} ${binaryPath} apply --config -`,
+        `echo '${configJson}' | ${sudoCmd} ${binaryPath} apply --config - --format json`,
       );
     }
   } catch (error: any) {